@@ -1,10 +1,30 @@
-use anyhow::Result;
-use tui_textarea::TextArea;
+use std::{
+    fs,
+    path::PathBuf,
+    rc::Rc,
+    time::{Duration, Instant}
+};
+
+use anyhow::{Context, Result};
+use tui_textarea::{CursorMove, TextArea};
 
 use crate::{
-    cli::Cli, jq::{
-        self, JqClient
-    }, tokens, scroll_text::ScrollText
+    backend::{BackendConfig, ErrorLocation, FilterBackend},
+    builtins,
+    cli::{Cli, Emit},
+    complete::{self, Completion},
+    diff::{self, DiffLine},
+    history::{self, History, HistorySearch},
+    inplace,
+    jq::{self, JqClient, JqOptions},
+    json::{self, JsonChange, PathSegment},
+    pipeline,
+    saved::{SaveForm, SavedPicker, SavedQueries, SavedQuery},
+    scroll_text::ScrollText,
+    shell,
+    tokens,
+    tree::JsonTree,
+    ui
 };
 
 #[derive(Debug)]
@@ -22,8 +42,14 @@ pub struct App {
 
     pub jq_client: JqClient,
 
+    /// The options we pass along to jq on every run
+    pub jq_options: JqOptions,
+
     /// True while the app should be running.
     pub is_running: bool,
+
+    /// True if the user quit with the abort key: nothing is emitted, and we exit with an error
+    pub aborted: bool,
     
     /// Present if there is some error message to display
     pub error: Option<ErrorPanel>,
@@ -33,26 +59,232 @@ pub struct App {
 
     /// Whether to colorize the filtered query
     pub colorize: bool,
+
+    /// Whether to run the query automatically after the user stops typing
+    pub live: bool,
+
+    /// How long the query must be left alone before it is run in live mode
+    pub debounce: Duration,
+
+    /// When the query was last edited, if it has not been submitted since
+    pub pending_edit: Option<Instant>,
+
+    /// The last query that was handed to jq
+    pub last_submitted: Option<String>,
+
+    /// True while the output of the running job is arriving in pieces
+    pub streaming: bool,
+
+    /// False once output has started arriving, until it has all arrived. Stays false if the job fails,
+    /// is cancelled or times out part way through: the output shown is then cut short, and is not
+    /// written, emitted or picked from
+    pub output_complete: bool,
+
+    /// Present while the user is stepping through the stages of the query
+    pub inspector: Option<Inspector>,
+
+    /// The queries from previous runs
+    pub history: History,
+
+    /// The name we record in the history for the input
+    pub input_name: String,
+
+    /// A query the user submitted, to be added to the history once jq runs it successfully
+    pub pending_history: Option<String>,
+
+    /// True once the query that last ran has gone into the history
+    remembered: bool,
+
+    /// The queries the user has saved by name
+    pub saved: SavedQueries,
+
+    /// The file the query was loaded from with --from-file, which it can be written back to
+    pub query_file: Option<PathBuf>,
+
+    /// The input file as it was given on the command line, `None` for stdin
+    pub input_filename: Option<PathBuf>,
+
+    /// What to print to stdout on exit
+    pub emit: Emit,
+
+    /// The file being edited in place, if any
+    pub in_place: Option<PathBuf>,
+
+    /// Whether to keep a backup of the file being edited in place
+    pub backup: bool,
+
+    /// What the query changed, shown instead of the output when the user asks for it
+    pub diff_view: Option<DiffView>,
+
+    /// The input formatted for the diff, with the options it was formatted with and whether the native evaluator
+    /// did it, kept until either changes
+    diff_base: Option<(JqOptions, bool, Rc<str>)>,
+
+    /// The key to match up objects in arrays by, when diffing by structure
+    pub match_key: Option<String>,
+
+    /// Whether keys go to the query editor or the result viewer
+    pub focus: Focus,
+
+    /// The structure of the output, if it is json. Gives the path of each line
+    pub tree: Option<JsonTree>,
+
+    /// Whether the output is shown as a tree that can be folded, rather than as text
+    pub tree_view: bool,
+
+    /// Whether long lines of text wrap onto the next rows, rather than being cut off
+    pub wrap: bool,
+
+    /// A short message about something that just happened, e.g. the query being written out
+    pub notice: Option<String>,
+
+    /// Present while a popup has the keyboard
+    pub popup: Option<Popup>,
+}
+
+/// Popups drawn over the result pane that take all the input while open
+#[derive(Debug)]
+pub enum Popup {
+    HistorySearch(HistorySearch),
+    SaveQuery(SaveForm),
+    SavedPicker(SavedPicker),
+    Completion(Completion),
+    ConfirmWrite(ConfirmWrite),
+}
+
+/// The changes that writing the file in place would make, waiting for the user to say yes
+#[derive(Debug)]
+pub struct ConfirmWrite {
+    pub path: PathBuf,
+    /// What the file will hold
+    pub contents: String,
+    /// The diff between what it holds now and the new contents
+    pub diff: DiffView,
+}
+
+/// Which pane the keys go to
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Focus {
+    Query,
+    Result,
+}
+
+/// How two versions of the json are compared
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffMode {
+    /// Line by line, as text
+    Lines,
+    /// Path by path, ignoring the order of keys
+    Structure,
+}
+
+/// A colored diff, ready to scroll through
+#[derive(Debug)]
+pub struct DiffView {
+    pub mode: DiffMode,
+    pub text: ScrollText<'static>,
+    pub added: usize,
+    pub removed: usize,
+    pub changed: usize,
+}
+
+impl DiffView {
+    pub fn new(old: &str, new: &str) -> DiffView {
+        let diff = diff::diff_lines(old, new);
+        let added = diff.iter().filter(|line| matches!(line, DiffLine::Added(_))).count();
+        let removed = diff.iter().filter(|line| matches!(line, DiffLine::Removed(_))).count();
+        let text = ScrollText::from_lines(ui::diff_to_lines(&diff::with_context(&diff, DIFF_CONTEXT)));
+        DiffView { mode: DiffMode::Lines, text, added, removed, changed: 0 }
+    }
+
+    pub fn structural(changes: &[JsonChange]) -> DiffView {
+        let count = |f: fn(&JsonChange) -> bool| changes.iter().filter(|change| f(change)).count();
+        DiffView {
+            mode: DiffMode::Structure,
+            text: ScrollText::from_lines(ui::json_changes_to_lines(changes)),
+            added: count(|change| matches!(change, JsonChange::Added { .. })),
+            removed: count(|change| matches!(change, JsonChange::Removed { .. })),
+            changed: count(|change| matches!(change, JsonChange::Changed { .. })),
+        }
+    }
+
+    /// A view with nothing but a note about why there is no diff to show
+    fn note(mode: DiffMode, note: &'static str) -> DiffView {
+        let text = ScrollText::from_lines(vec![ui::diff_note(note)]);
+        DiffView { mode, text, added: 0, removed: 0, changed: 0 }
+    }
+}
+
+/// How many columns the text moves when scrolled sideways
+const COLUMN_STEP: isize = 8;
+
+/// How many unchanged lines to show around each change in a diff
+const DIFF_CONTEXT: usize = 3;
+
+/// Roughly how much streamed output (in bytes) we take in before drawing again
+const MAX_CONTENT_PER_UPDATE: usize = 256 * 1024;
+
+/// Steps through the output of each stage of the query's pipeline
+#[derive(Debug)]
+pub struct Inspector {
+    /// The query for each stage: the first stage, the first two stages, and so on
+    pub stages: Vec<String>,
+    /// Which stage is showing
+    pub current: usize,
 }
 
 #[derive(Debug)]
 pub struct ErrorPanel {
     pub title: String,
     pub failure: String,
+    /// The part of the query to underline
+    pub location: Option<ErrorLocation>,
 }
 
 impl App {
-    pub fn init(cli: &Cli, original: &'static str) -> App {
+    pub fn init(cli: &Cli, config: &BackendConfig, original: &'static str, history: History, saved: SavedQueries) -> App {
+        let timeout = match cli.timeout_secs {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        };
         App {
             original,
             scroll_text: ScrollText::from(original.to_string()),
             filtered: original.to_string(),
             query_editor: TextArea::default(),
-            jq_client: JqClient::new(),
+            jq_client: JqClient::new(FilterBackend::from_cli(cli, config), cli.engine, timeout, original),
+            jq_options: JqOptions::from_cli(cli),
             is_running: true,
+            aborted: false,
             error: None,
             clear_screen: false,
             colorize: cli.colorize,
+            live: cli.live,
+            debounce: Duration::from_millis(cli.debounce_ms),
+            pending_edit: None,
+            last_submitted: None,
+            streaming: false,
+            output_complete: true,
+            inspector: None,
+            history,
+            input_name: history::input_name(cli.input_path()),
+            pending_history: None,
+            remembered: false,
+            saved,
+            query_file: cli.from_file.clone(),
+            input_filename: cli.input_path().map(PathBuf::from),
+            in_place: cli.in_place.clone(),
+            backup: cli.backup,
+            diff_view: None,
+            diff_base: None,
+            match_key: cli.match_key.clone(),
+            focus: Focus::Query,
+            tree: None,
+            tree_view: false,
+            wrap: cli.wrap,
+            emit: cli.emit,
+            notice: None,
+            popup: None,
         }
     }
 
@@ -60,31 +292,70 @@ impl App {
         self.filtered.as_str()
     }
 
-    pub fn query_content(&self) -> &str {
-        self.query_editor.lines()[0].as_str()
+    /// The whole query, all of its lines
+    pub fn query_content(&self) -> String {
+        self.query_editor.lines().join("\n")
     }
 
     pub fn update(&mut self, _cli: &Cli) -> Result<()> {
 
-        // check if the job is done running
-        if let Some(output) = self.jq_client.try_recv_output() {
+        // in live mode, run the query once the user has stopped typing for long enough
+        if let Some(edited_at) = self.pending_edit {
+            if edited_at.elapsed() >= self.debounce {
+                self.submit_query();
+            }
+        }
+
+        // pick up whatever the job has produced since last time, a bounded amount so we keep drawing
+        let mut taken_in = 0;
+        while taken_in < MAX_CONTENT_PER_UPDATE {
+            let Some(output) = self.jq_client.try_recv_output() else { break; };
 
             match output {
+                 jq::JqOutput::Partial { json_content } => {
+                     taken_in += json_content.len();
+                     if self.streaming {
+                         self.append_display_content(json_content);
+                     } else {
+                         log::info!("jq started producing output, replacing our filtered content now");
+                         self.streaming = true;
+                         self.output_complete = false;
+                         self.error = None;
+                         self.set_display_content(json_content);
+                     }
+                     continue;
+                 }
                  jq::JqOutput::Success { json_content } => {
                      log::info!("received a successful response from jq, changing our filtered content now");
                      self.error = None;
-                     self.set_display_content(json_content);
+                     self.output_complete = true;
+                     if let Some(query) = self.pending_history.take() {
+                         self.remember(&query);
+                     }
+                     if self.streaming {
+                         self.append_display_content(json_content);
+                     } else {
+                         self.set_display_content(json_content);
+                     }
                  }
-                 jq::JqOutput::Failure { title, failure } => {
-                     // do NOT overwrite previous content on a fail, just show last good state
+                 jq::JqOutput::Failure { title, failure, location } => {
+                     // whatever is on screen stays there: the output of an earlier query, or as much of this
+                     // query's output as streamed in before the failure, which stays marked incomplete
                      log::info!("received an error from jq");
+                     self.pending_history = None;
+                     if self.streaming {
+                         // the output has stopped arriving, so the diff can catch up with as much as there is
+                         self.refresh_diff_view();
+                     }
                      self.error = Some(ErrorPanel {
                          title,
-                         failure
+                         failure,
+                         location,
                     });
                  }
              }
 
+            self.streaming = false;
             self.clear_screen = true;
         }
 
@@ -93,31 +364,697 @@ impl App {
 
     /// Called when the user presses enter. Runs the query again
     pub fn submit_query(&mut self) {
+        self.inspector = None;
+        let query_content = self.query_content();
+        self.run_query(query_content);
+    }
+
+    /// Called when the user presses enter. Runs the query and adds it to the history if it works
+    pub fn confirm_query(&mut self) {
+        self.submit_query();
+        self.pending_history = self.last_submitted.clone();
+    }
+
+    /// Called as the app exits. In live mode the user may never have pressed enter,
+    /// so the query that is showing goes into the history too, if it ran successfully
+    pub fn finish(&mut self) {
+        if self.result_is_current() && !self.remembered {
+            let query = self.query_content();
+            self.remember(&query);
+        }
+    }
+
+    fn remember(&mut self, query: &str) {
+        if let Err(e) = self.history.add(&self.input_name, query) {
+            log::error!("could not save the query to the history: {e:?}");
+        }
+        self.remembered = true;
+    }
+
+    fn run_query(&mut self, query: String) {
         log::info!("submitting query to jq");
-        let query_content = self.query_content().to_string();
-        self.jq_client.submit_query(self.original, query_content)
+        self.pending_edit = None;
+        self.pending_history = None;
+        self.remembered = false;
+        self.last_submitted = Some(query.clone());
+        self.streaming = false;
+        self.jq_client.submit_query(query, &self.jq_options)
+    }
+
+    /// Called when the user toggles the pipeline inspector.
+    /// Starts by showing the output of the first stage.
+    pub fn toggle_inspector(&mut self) {
+        if self.inspector.is_some() {
+            log::info!("leaving the pipeline inspector");
+            self.submit_query();
+            return;
+        }
+
+        let stages = pipeline::stage_prefixes(&self.query_content());
+        log::info!("inspecting {} stages", stages.len());
+        if stages.is_empty() {
+            return;
+        }
+        self.inspector = Some(Inspector { stages, current: 0 });
+        self.run_inspected_stage();
+    }
+
+    /// Called when the user steps through the pipeline. Negative steps go back towards the first stage
+    pub fn step_inspector(&mut self, step: isize) {
+        let Some(inspector) = &mut self.inspector else { return; };
+        let last = inspector.stages.len() - 1;
+        let current = inspector.current
+            .saturating_add_signed(step)
+            .min(last);
+        // already at the first or last stage, there is nothing new to run
+        if current == inspector.current {
+            return;
+        }
+        inspector.current = current;
+        self.run_inspected_stage();
+    }
+
+    fn run_inspected_stage(&mut self) {
+        let Some(inspector) = &self.inspector else { return; };
+        let query = inspector.stages[inspector.current].clone();
+        self.run_query(query);
+    }
+
+    /// Called when the user flips one of the jq flags. Reruns the query so the output reflects it
+    pub fn toggle_option(&mut self, toggle: fn(&mut JqOptions) -> &mut bool) {
+        let flag = toggle(&mut self.jq_options);
+        *flag = !*flag;
+        log::info!("jq options are now {:?}", self.jq_options.flags());
+        match self.inspector {
+            Some(_) => self.run_inspected_stage(),
+            None => self.submit_query(),
+        }
+    }
+
+    /// Called when the user presses the cancel key. Kills the running jq, if any
+    pub fn cancel_query(&mut self) {
+        if self.jq_client.cancel() {
+            // any output that streamed in stays marked incomplete
+            self.streaming = false;
+            self.error = Some(ErrorPanel {
+                title: "cancelled".to_string(),
+                failure: "the running query was cancelled".to_string(),
+                location: None,
+            });
+        }
+    }
+
+    /// Replaces the whole query, leaving the cursor at the end
+    pub fn set_query(&mut self, query: &str) {
+        let mut editor = TextArea::from(query.lines());
+        editor.move_cursor(CursorMove::Bottom);
+        editor.move_cursor(CursorMove::End);
+        self.query_editor = editor;
+        self.query_changed();
+    }
+
+    /// Called when the user wants another line in the query (alt-enter)
+    pub fn insert_newline(&mut self) {
+        self.query_editor.insert_newline();
+        self.query_edited();
+    }
+
+    /// Called when the user moves between the lines of the query
+    pub fn move_query_cursor(&mut self, movement: CursorMove) {
+        self.query_editor.move_cursor(movement);
+    }
+
+    /// Called when the user recalls the previous query from the history (alt-,)
+    pub fn history_older(&mut self) {
+        let current = self.query_content();
+        if let Some(query) = self.history.older(&current) {
+            let query = query.to_string();
+            self.set_query(&query);
+        }
+    }
+
+    /// Called when the user recalls the next query from the history (alt-.)
+    pub fn history_newer(&mut self) {
+        if let Some(query) = self.history.newer() {
+            let query = query.to_string();
+            self.set_query(&query);
+        }
+    }
+
+    /// Called when the user opens the history search (alt-/)
+    pub fn open_history_search(&mut self) {
+        self.popup = Some(Popup::HistorySearch(HistorySearch::new(&self.history)));
+    }
+
+    /// Called when the user picks a query from the history search
+    pub fn accept_history_search(&mut self) {
+        let Some(Popup::HistorySearch(search)) = self.popup.take() else { return; };
+        if let Some(query) = search.selected_query() {
+            self.history.stop_walking();
+            self.set_query(query);
+        }
+    }
+
+    /// Called when the user wants to save the current query under a name (ctrl-s)
+    pub fn open_save_form(&mut self) {
+        self.popup = Some(Popup::SaveQuery(SaveForm::new()));
+    }
+
+    /// Called when the user confirms the save form
+    pub fn accept_save_form(&mut self) {
+        let Some(Popup::SaveQuery(form)) = self.popup.take() else { return; };
+        let name = form.name.trim().to_string();
+        if name.is_empty() {
+            // nothing to save it under, let them try again
+            self.popup = Some(Popup::SaveQuery(form));
+            return;
+        }
+        let saved = SavedQuery {
+            name,
+            description: form.description.trim().to_string(),
+            query: self.query_content(),
+        };
+        log::info!("saving query as {:?}", saved.name);
+        if let Err(e) = self.saved.insert(saved) {
+            self.error = Some(ErrorPanel {
+                title: "could not save the query".to_string(),
+                failure: format!("{e:?}"),
+                location: None,
+            });
+        }
+    }
+
+    /// Called when the user wants to leave with a command for their shell (alt-x)
+    pub fn quit_with_command(&mut self) {
+        self.emit = Emit::Command;
+        self.is_running = false;
+    }
+
+    /// Called when the user quits with the abort key (ctrl-q)
+    pub fn abort(&mut self) {
+        log::info!("aborting");
+        self.jq_client.cancel();
+        self.aborted = true;
+        self.is_running = false;
+    }
+
+    /// True if what is shown is all of the output of the query in the editor, which ran successfully
+    pub fn result_is_current(&self) -> bool {
+        self.error.is_none()
+            && !self.jq_client.is_running()
+            && self.inspector.is_none()
+            && self.pending_edit.is_none()
+            && self.output_complete
+            && self.last_submitted == Some(self.query_content())
+    }
+
+    /// A command line that runs the current query the way we do, ready to paste into a script
+    pub fn shell_command(&self) -> String {
+        let mut argv = self.jq_client.backend.command(&self.query_content(), &self.jq_options);
+        if let Some(path) = &self.input_filename {
+            argv.push(path.display().to_string());
+        }
+        shell::command_line(&argv)
+    }
+
+    /// Called when the user moves between the query editor and the result viewer (F4)
+    pub fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Focus::Query => Focus::Result,
+            Focus::Result => Focus::Query,
+        };
+        log::info!("focus is now on {:?}", self.focus);
+    }
+
+    /// Called when the user switches between the output as text and as a tree that can be folded
+    pub fn toggle_tree(&mut self) {
+        if self.tree_view {
+            log::info!("showing the output as text again");
+            self.tree_view = false;
+            return;
+        }
+        if self.tree.is_none() && !self.output_complete {
+            self.notice = Some("the output is incomplete, it can be shown as a tree once all of it is there".to_string());
+            return;
+        }
+        if self.tree.is_none() {
+            self.notice = Some("the output is not json, so it can not be shown as a tree".to_string());
+            return;
+        }
+        self.tree_view = true;
+    }
+
+    /// The tree, while the output is being shown as one
+    pub fn shown_tree(&self) -> Option<&JsonTree> {
+        self.tree.as_ref().filter(|_| self.tree_view)
+    }
+    pub fn shown_tree_mut(&mut self) -> Option<&mut JsonTree> {
+        self.tree.as_mut().filter(|_| self.tree_view)
+    }
+
+    fn refresh_tree(&mut self) {
+        if self.jq_options.raw_output {
+            self.tree = None;
+            return;
+        }
+        // the folds do not carry over, the new output may look nothing like the old one
+        self.tree = match JsonTree::parse(&self.filtered) {
+            Ok(tree) => Some(tree),
+            Err(e) => {
+                log::info!("the output has no structure to show: {e}");
+                None
+            }
+        };
+    }
+
+    /// Called when the user picks the value under the cursor in the viewer.
+    /// Puts its path into the query at the cursor, or makes the whole query pick it out
+    pub fn pick_path(&mut self, replace: bool) {
+        if !self.output_complete {
+            self.notice = Some("the output is incomplete, run the query again to pick from it".to_string());
+            return;
+        }
+        // the path would be into one of the values, but after the query it would be followed into all of them
+        if self.tree.as_ref().is_some_and(|tree| tree.value_count() > 1) {
+            self.notice = Some("the output is more than one value, so no one path picks this out".to_string());
+            return;
+        }
+        let Some(path) = self.cursor_path() else {
+            self.notice = Some("there is no json value here to pick".to_string());
+            return;
+        };
+        let path = json::path_to_string(&path);
+        log::info!("picked {path}");
+        self.focus = Focus::Query;
+        if !replace {
+            self.query_editor.insert_str(&path);
+            self.query_edited();
+            return;
+        }
+
+        // the path is into the output, so it goes after the query that made the output
+        let query = match self.last_submitted.as_deref().map(str::trim) {
+            None | Some("") | Some(".") => path,
+            Some(query) => format!("{query} | {path}"),
+        };
+        self.set_query(&query);
+        self.submit_query();
+    }
+
+    /// The path to the value under the cursor in the tree, or on the current line of the text
+    /// (the top line, while the query has focus)
+    fn cursor_path(&self) -> Option<Vec<PathSegment>> {
+        let tree = self.tree.as_ref()?;
+        let node = match self.tree_view {
+            true => tree.cursor_node()?,
+            // compact output has a whole value on each line
+            false if self.jq_options.compact_output => return None,
+            false => {
+                let line = match self.focus {
+                    Focus::Result => self.scroll_text.cursor(),
+                    Focus::Query => self.scroll_text.line_offset(),
+                };
+                tree.node_at_line(line)?
+            }
+        };
+        Some(tree.path(node))
+    }
+
+    /// Where the viewer is in the output, like `.BaselineIdentities[4].OperatingSystem`
+    pub fn breadcrumb(&self) -> Option<String> {
+        if self.diff_view.is_some() {
+            return None;
+        }
+        self.cursor_path().map(|path| json::path_to_string(&path))
+    }
+
+    /// Called when the user switches between the output and what the query changed (F3).
+    /// Goes from the output, to the diff line by line, to the diff by structure, and back
+    pub fn toggle_diff_view(&mut self) {
+        let mode = match self.diff_view.take().map(|view| view.mode) {
+            None => DiffMode::Lines,
+            Some(DiffMode::Lines) => DiffMode::Structure,
+            Some(DiffMode::Structure) => {
+                log::info!("showing the output again");
+                return;
+            }
+        };
+        log::info!("showing the diff against the input, mode = {mode:?}");
+        self.diff_view = Some(self.diff_against_input(mode));
+    }
+
+    fn refresh_diff_view(&mut self) {
+        if let Some(mode) = self.diff_view.as_ref().map(|view| view.mode) {
+            self.diff_view = Some(self.diff_against_input(mode));
+        }
+    }
+
+    fn diff_against_input(&mut self, mode: DiffMode) -> DiffView {
+        let mut view = match mode {
+            DiffMode::Lines => {
+                let base = self.diff_base();
+                DiffView::new(&base, &self.filtered)
+            }
+            DiffMode::Structure => self.structural_diff(),
+        };
+        view.text.set_wrap(self.wrap);
+        view
+    }
+
+    /// Compares the input and the output by structure, which only works when both are a single json value
+    fn structural_diff(&mut self) -> DiffView {
+        let Some(input) = self.jq_client.wait_for_input(self.jq_options.slurp) else {
+            return DiffView::note(DiffMode::Structure, "the input is not a single json value, so it can not be compared by structure");
+        };
+        let Ok(output) = json::loads(&self.filtered) else {
+            return DiffView::note(DiffMode::Structure, "the output is not a single json value, so it can not be compared by structure");
+        };
+        DiffView::structural(&json::diff(input, &output, self.match_key.as_deref()))
+    }
+
+    /// The input, formatted the way the output is and by whatever formatted the output, so the diff only shows
+    /// what the query changed. Empty if it can not be formatted
+    fn diff_base(&mut self) -> Rc<str> {
+        let mut options = self.jq_options.clone();
+        options.null_input = false;
+        let natively = self.jq_client.answered_natively;
+        if let Some((formatted_with, formatted_natively, base)) = &self.diff_base {
+            if *formatted_with == options && *formatted_natively == natively {
+                return base.clone();
+            }
+        }
+        let base: Rc<str> = self.jq_client.format_input(&options)
+            .unwrap_or_default()
+            .into();
+        self.diff_base = Some((options, natively, base.clone()));
+        base
+    }
+
+    /// Called when the user wants to write the output back over the file being edited in place (F2).
+    /// Shows what would change first
+    pub fn request_write(&mut self) {
+        let Some(path) = self.in_place.clone() else {
+            self.error = Some(ErrorPanel {
+                title: "not editing a file".to_string(),
+                failure: "start with -i <file.json> to edit a file in place".to_string(),
+                location: None,
+            });
+            return;
+        };
+        if !self.result_is_current() {
+            self.error = Some(ErrorPanel {
+                title: "nothing to write yet".to_string(),
+                failure: "the whole query, as it is in the editor, has to run successfully before its output can be written".to_string(),
+                location: None,
+            });
+            return;
+        }
+        if self.filtered == self.original {
+            self.notice = Some("no changes to write".to_string());
+            return;
+        }
+
+        let contents = self.filtered.clone();
+        let diff = DiffView::new(self.original, &contents);
+        self.popup = Some(Popup::ConfirmWrite(ConfirmWrite { path, contents, diff }));
+    }
+
+    /// Called when the user agrees to the changes. Writes the file and quits
+    pub fn confirm_write(&mut self) {
+        let Some(Popup::ConfirmWrite(confirm)) = self.popup.take() else { return; };
+        match inplace::replace_file(&confirm.path, &confirm.contents, self.backup) {
+            Ok(_) => {
+                // the result went to the file already
+                if self.emit == Emit::Result {
+                    self.emit = Emit::None;
+                }
+                self.is_running = false;
+            }
+            Err(e) => {
+                self.error = Some(ErrorPanel {
+                    title: format!("could not write {}", confirm.path.display()),
+                    failure: format!("{e:?}"),
+                    location: None,
+                });
+            }
+        }
+    }
+
+    /// Called when the user writes the query back to its file (alt-w)
+    pub fn write_query_file(&mut self) {
+        let Some(path) = self.query_file.clone() else {
+            self.error = Some(ErrorPanel {
+                title: "nowhere to write the query".to_string(),
+                failure: "start with --from-file <path.jq> to edit a filter file".to_string(),
+                location: None,
+            });
+            return;
+        };
+        let mut query = self.query_content();
+        query.push('\n');
+        let result = fs::write(&path, query)
+            .with_context(|| format!("writing query to {}", path.display()));
+        match result {
+            Ok(()) => {
+                log::info!("wrote query to {}", path.display());
+                self.notice = Some(format!("wrote {}", path.display()));
+            }
+            Err(e) => {
+                self.error = Some(ErrorPanel {
+                    title: "could not write the query".to_string(),
+                    failure: format!("{e:?}"),
+                    location: None,
+                });
+            }
+        }
+    }
+
+    /// Called when the user opens the saved query picker (ctrl-o)
+    pub fn open_saved_picker(&mut self) {
+        self.popup = Some(Popup::SavedPicker(SavedPicker::new(&self.saved)));
+    }
+
+    /// Called when the user picks a saved query to load
+    pub fn accept_saved_picker(&mut self) {
+        let Some(Popup::SavedPicker(picker)) = self.popup.take() else { return; };
+        if let Some(saved) = picker.selected_query() {
+            log::info!("loading saved query {:?}", saved.name);
+            self.history.stop_walking();
+            self.set_query(&saved.query);
+        }
+    }
+
+    /// Called when the user deletes the selected query from the picker
+    pub fn delete_selected_saved(&mut self) {
+        let Some(Popup::SavedPicker(picker)) = &mut self.popup else { return; };
+        let Some(name) = picker.selected_query().map(|saved| saved.name.clone()) else { return; };
+        log::info!("deleting saved query {name:?}");
+        if let Err(e) = self.saved.remove(&name) {
+            self.error = Some(ErrorPanel {
+                title: "could not delete the saved query".to_string(),
+                failure: format!("{e:?}"),
+                location: None,
+            });
+        }
+        picker.refilter(&self.saved);
+    }
+
+    /// The query from the start up to the cursor
+    pub fn text_before_cursor(&self) -> String {
+        let (row, col) = self.query_editor.cursor();
+        let lines = self.query_editor.lines();
+        let mut text = lines[..row].iter()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>();
+        text.extend(lines[row].chars().take(col));
+        text
+    }
+
+    /// Called when the user asks for completions (tab). On a name, that is the builtins it could be,
+    /// otherwise the next step of the path. A single completion is filled in right away,
+    /// otherwise they are offered in a popup
+    pub fn complete(&mut self) {
+        let prefix = self.text_before_cursor();
+        let completion = if complete::word_before(&prefix).is_some() {
+            complete::complete_builtin(&prefix, &self.jq_client.backend.builtins)
+        } else {
+            let options = self.jq_options.clone();
+            let Some(input) = self.jq_client.parsed_input(options.slurp) else {
+                if self.jq_client.input_is_parsed() {
+                    log::info!("no completions, the input does not parse");
+                } else {
+                    self.notice = Some("still reading the input, try again in a moment".to_string());
+                }
+                return;
+            };
+            complete::complete_path(&prefix, input, &options)
+        };
+        let Some(completion) = completion else {
+            log::info!("no completions for {prefix:?}");
+            return;
+        };
+        log::info!("{} completions for {prefix:?}", completion.items.len());
+        if completion.items.len() == 1 {
+            self.apply_completion(&completion);
+        } else {
+            self.popup = Some(Popup::Completion(completion));
+        }
+    }
+
+    /// Called when the user picks one of the completions in the popup
+    pub fn accept_completion(&mut self) {
+        let Some(Popup::Completion(completion)) = self.popup.take() else { return; };
+        self.apply_completion(&completion);
+    }
+
+    fn apply_completion(&mut self, completion: &Completion) {
+        for _ in 0..completion.replace_len {
+            self.query_editor.delete_char();
+        }
+        let item = completion.selected_item();
+        self.query_editor.insert_str(&item.text);
+        for _ in 0..item.cursor_back {
+            self.query_editor.move_cursor(CursorMove::Back);
+        }
+        self.query_edited();
+    }
+
+    /// The signatures of the builtin the cursor is on or in a call to, e.g. `sub/2, sub/3`
+    pub fn signature_hint(&self) -> Option<String> {
+        let prefix = self.text_before_cursor();
+        let builtins = &self.jq_client.backend.builtins;
+        complete::word_before(&prefix)
+            .and_then(|name| builtins::signatures(builtins, name))
+            .or_else(|| {
+                let name = complete::enclosing_call(&prefix)?;
+                builtins::signatures(builtins, name)
+            })
+    }
+
+    /// Called whenever the user changes the text of the query.
+    /// In live mode, this (re)starts the debounce timer.
+    pub fn query_edited(&mut self) {
+        self.history.stop_walking();
+        self.notice = None;
+        self.query_changed();
+    }
+
+    fn query_changed(&mut self) {
+        // the stages we were stepping through are not the query anymore
+        self.inspector = None;
+        if !self.live {
+            return;
+        }
+        if self.last_submitted == Some(self.query_content()) {
+            // edited back to what is already showing, nothing to run
+            self.pending_edit = None;
+            return;
+        }
+        self.pending_edit = Some(Instant::now());
     }
 
     pub fn set_display_content(&mut self, content: String) {
         // todo: do we need this?
         self.filtered = content.clone();
 
-        if self.colorize {
+        // raw output is not json, so there is nothing sensible to colorize
+        if self.colorize && !self.jq_options.raw_output {
             let tokens = tokens::tokenize(content.as_str());
             self.scroll_text = ScrollText::from_tokens(tokens.as_slice());
         } else {
             self.scroll_text = ScrollText::from_content(content);
         }
+        self.scroll_text.set_wrap(self.wrap);
+        self.output_changed();
+    }
+
+    /// Adds more content from a job that is still running, keeping the scroll position
+    pub fn append_display_content(&mut self, content: String) {
+        if self.colorize && !self.jq_options.raw_output {
+            let tokens = tokens::tokenize(content.as_str());
+            self.scroll_text.append_tokens(tokens.as_slice());
+        } else {
+            self.scroll_text.append_content(content.as_str());
+        }
+        self.filtered.push_str(&content);
+        self.output_changed();
+    }
+
+    fn output_changed(&mut self) {
+        // diffing and parsing are slow on big outputs, so they wait until all of the output has arrived
+        if self.output_complete {
+            self.refresh_diff_view();
+            self.refresh_tree();
+        } else {
+            self.tree = None;
+        }
+    }
+
+    /// Called when the user moves the cursor of the result viewer (up/down while it has focus)
+    pub fn move_result_cursor(&mut self, delta: isize) {
+        self.with_shown_result(|text| text.move_cursor(delta), |tree| tree.move_cursor(delta));
+    }
+    /// Called when the user moves the cursor of the result viewer a screenful (page up/page down)
+    pub fn move_result_cursor_pages(&mut self, pages: isize) {
+        self.with_shown_result(|text| text.move_cursor_pages(pages), |tree| tree.move_cursor_pages(pages));
+    }
+    /// Called when the user moves the cursor of the result viewer to the top (home)
+    pub fn result_cursor_to_start(&mut self) {
+        self.with_shown_result(ScrollText::cursor_to_start, JsonTree::cursor_to_start);
+    }
+    /// Called when the user moves the cursor of the result viewer to the bottom (end)
+    pub fn result_cursor_to_end(&mut self) {
+        self.with_shown_result(ScrollText::cursor_to_end, JsonTree::cursor_to_end);
+    }
+
+    /// Called when the user scrolls the text sideways (left/right while the viewer has focus)
+    pub fn scroll_result_columns(&mut self, delta: isize) {
+        self.with_shown_result(|text| text.scroll_columns(delta * COLUMN_STEP), |_| {});
+    }
+
+    /// Called when the user switches between cutting long lines off and wrapping them (w)
+    pub fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        log::info!("wrapping long lines: {}", self.wrap);
+        self.scroll_text.set_wrap(self.wrap);
+        if let Some(diff) = &mut self.diff_view {
+            diff.text.set_wrap(self.wrap);
+        }
+    }
+
+    /// Called when the user starts or stops selecting lines of the output as text (v)
+    pub fn toggle_selection(&mut self) {
+        self.with_shown_result(ScrollText::toggle_selection, |_| {});
+    }
+
+    /// Called when the user presses esc in the result viewer. Drops the selection, if there is one,
+    /// or goes back to the query
+    pub fn leave_result(&mut self) {
+        let mut cleared = false;
+        self.with_shown_result(|text| cleared = text.clear_selection(), |_| {});
+        if !cleared {
+            self.focus = Focus::Query;
+        }
+    }
+
+    /// Acts on whatever the result viewer is showing: the diff, the tree or the text
+    fn with_shown_result(&mut self, text: impl FnOnce(&mut ScrollText<'static>), tree: impl FnOnce(&mut JsonTree)) {
+        let shown_tree = self.tree.as_mut().filter(|_| self.tree_view);
+        match (&mut self.diff_view, shown_tree) {
+            (Some(diff), _) => text(&mut diff.text),
+            (None, Some(shown_tree)) => tree(shown_tree),
+            (None, None) => text(&mut self.scroll_text),
+        }
     }
 
     /// Called when the user scrolls the text area
     pub fn scroll_up(&mut self) {
         log::info!("scroll up");
-        self.scroll_text.scroll_up();
+        self.with_shown_result(ScrollText::scroll_up, |tree| tree.move_cursor(-1));
     }
     /// Called when the user scrolls the text area
     pub fn scroll_down(&mut self) {
         log::info!("scroll down");
-        self.scroll_text.scroll_down();
+        self.with_shown_result(ScrollText::scroll_down, |tree| tree.move_cursor(1));
     }
 }
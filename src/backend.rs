@@ -0,0 +1,546 @@
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use subprocess::{Exec, Redirection};
+
+use crate::{
+    builtins::{self, Builtin},
+    cli::Cli,
+    jq::JqOptions,
+    json::{self, JsonDataType}
+};
+
+const CONFIG_FILE_NAME: &str = "config.json";
+
+/// The jq implementations that we know how to drive
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum BackendKind {
+    /// The reference implementation, https://github.com/jqlang/jq
+    Jq,
+    /// The go implementation, https://github.com/itchyny/gojq
+    Gojq,
+    /// The rust implementation, https://github.com/01mf02/jaq
+    Jaq,
+}
+
+impl BackendKind {
+    pub fn exe_name(self) -> &'static str {
+        match self {
+            BackendKind::Jq => "jq",
+            BackendKind::Gojq => "gojq",
+            BackendKind::Jaq => "jaq",
+        }
+    }
+
+    /// Guess the kind of a binary from its file name, so `--jq-path /opt/bin/gojq` does the right thing
+    fn guess_from_path(path: &Path) -> BackendKind {
+        let name = path.file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("");
+        if name.contains("gojq") {
+            BackendKind::Gojq
+        } else if name.contains("jaq") {
+            BackendKind::Jaq
+        } else {
+            BackendKind::Jq
+        }
+    }
+}
+
+/// The backend to use when the command line does not say, kept in the config directory as
+/// `{"backend": "gojq", "jq_path": "/opt/bin/gojq"}`. Both keys are optional
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BackendConfig {
+    pub backend: Option<BackendKind>,
+    pub jq_path: Option<PathBuf>,
+}
+
+impl BackendConfig {
+    /// Reads the config in `config_dir`. A missing file is just no config
+    pub fn load(config_dir: &Path) -> Result<BackendConfig> {
+        let path = config_dir.join(CONFIG_FILE_NAME);
+        if !path.exists() {
+            return Ok(BackendConfig::default());
+        }
+        let source = fs::read_to_string(&path)
+            .with_context(|| format!("reading config from {}", path.display()))?;
+        let config = BackendConfig::parse(&source)
+            .with_context(|| format!("parsing config in {}", path.display()))?;
+        log::info!("loaded config from {}: {config:?}", path.display());
+        Ok(config)
+    }
+
+    fn parse(source: &str) -> Result<BackendConfig> {
+        let json = json::loads(source)?;
+        let JsonDataType::Object { entries } = json.ty() else {
+            bail!("expected an object, found {}", json.type_name());
+        };
+        let mut config = BackendConfig::default();
+        for (key, value) in entries {
+            let key = key.name();
+            let Some(text) = value.as_str() else {
+                bail!("{key:?} should be a string, found {}", value.type_name());
+            };
+            match key.as_ref() {
+                "backend" => {
+                    let kind = BackendKind::from_str(&text, true)
+                        .map_err(|_| anyhow!("unknown backend {text:?}, expected one of jq, gojq or jaq"))?;
+                    config.backend = Some(kind);
+                }
+                "jq_path" => config.jq_path = Some(PathBuf::from(text.as_ref())),
+                _ => log::warn!("ignoring unknown config key {key:?}"),
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// jq: `syntax error, unexpected $end (Unix shell quoting issues?) at <top-level>, line 1`
+/// newer versions also add `, column 6`
+static JQ_LOCATION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<msg>.*?)(?: \(Unix shell quoting issues\?\))? at <top-level>, line (?P<line>\d+)(?:, column (?P<col>\d+))?$")
+        .expect("compile regex")
+});
+/// jq: `foo/0 is not defined`, `$x is not defined`
+static JQ_NOT_DEFINED: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^(?P<name>\$?[a-zA-Z_][a-zA-Z0-9_:]*)(?:/\d+)? is not defined").expect("compile regex")
+});
+/// jaq: `╭─[<unknown>:1:6]`
+static JAQ_LOCATION: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\[[^\]]*:(?P<line>\d+):(?P<col>\d+)\]").expect("compile regex")
+});
+
+/// Where in the query an error is, as a range of chars on one line
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct ErrorLocation {
+    /// 0 based line of the query
+    pub line: usize,
+    /// 0 based char column of the first offending char
+    pub start: usize,
+    /// 0 based char column just past the last offending char
+    pub end: usize,
+}
+
+/// What we could make of the error output of the backend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryError {
+    /// One line, to be used as a title
+    pub summary: String,
+    pub location: Option<ErrorLocation>,
+}
+
+/// The program that actually runs the user's filters
+#[derive(Debug, Clone)]
+pub struct FilterBackend {
+    pub kind: BackendKind,
+    /// The executable to run
+    pub exe: PathBuf,
+    /// What the executable reported from `--version`, if it could be run at all
+    pub version: Option<String>,
+    /// The builtin functions, as the executable lists them, or the bundled list if it can not
+    pub builtins: Vec<Builtin>,
+}
+
+impl FilterBackend {
+    /// The backend the command line asks for, or the config if the command line names neither a backend nor a path
+    pub fn from_cli(cli: &Cli, config: &BackendConfig) -> FilterBackend {
+        let (backend, jq_path) = match (cli.backend, &cli.jq_path) {
+            (None, None) => (config.backend, &config.jq_path),
+            _ => (cli.backend, &cli.jq_path),
+        };
+        let kind = match (backend, jq_path) {
+            (Some(kind), _) => kind,
+            (None, Some(path)) => BackendKind::guess_from_path(path),
+            (None, None) => BackendKind::Jq,
+        };
+        let exe = jq_path
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(kind.exe_name()));
+
+        let mut backend = FilterBackend {
+            kind,
+            exe,
+            version: None,
+            builtins: Vec::new(),
+        };
+        backend.version = backend.detect_version();
+        backend.builtins = match backend.version {
+            Some(_) => backend.detect_builtins(),
+            None => builtins::bundled(),
+        };
+        log::info!("using backend {} ({:?}) at {}", backend.name(), backend.version, backend.exe.display());
+        backend
+    }
+
+    /// A short name for the ui, e.g. `jq`
+    pub fn name(&self) -> &'static str {
+        self.kind.exe_name()
+    }
+
+    fn detect_version(&self) -> Option<String> {
+        let result = Exec::cmd(&self.exe)
+            .arg("--version")
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Merge)
+            .capture();
+        match result {
+            Ok(capture) if capture.success() => {
+                let out = capture.stdout_str();
+                out.lines().next().map(|l| l.trim().to_string())
+            }
+            Ok(capture) => {
+                log::warn!("{} --version exited with {:?}", self.exe.display(), capture.exit_status);
+                None
+            }
+            Err(e) => {
+                log::warn!("could not run {}: {e}", self.exe.display());
+                None
+            }
+        }
+    }
+
+    fn detect_builtins(&self) -> Vec<Builtin> {
+        let result = Exec::cmd(&self.exe)
+            .args(&["-n", "-r", "builtins[]"])
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture();
+        match result {
+            Ok(capture) if capture.success() => {
+                let builtins = builtins::parse_list(&capture.stdout_str());
+                log::info!("{} lists {} builtins", self.exe.display(), builtins.len());
+                builtins
+            }
+            _ => {
+                log::info!("{} can not list its builtins, using the bundled list", self.exe.display());
+                builtins::bundled()
+            }
+        }
+    }
+
+    /// `input` as the backend prints it with `options`, run through `.` and waited on.
+    /// `None` if it could not be run or failed
+    pub fn format(&self, input: &str, options: &JqOptions) -> Option<String> {
+        let result = Exec::cmd(&self.exe)
+            .args(&self.command(".", options)[1..])
+            .stdin(input)
+            .stdout(Redirection::Pipe)
+            .stderr(Redirection::Pipe)
+            .capture();
+        match result {
+            Ok(capture) if capture.success() => Some(capture.stdout_str()),
+            Ok(capture) => {
+                log::warn!("{} could not format the input: {}", self.exe.display(), capture.stderr_str().trim());
+                None
+            }
+            Err(e) => {
+                log::warn!("could not run {}: {e}", self.exe.display());
+                None
+            }
+        }
+    }
+
+    /// The full argv to run `query` with `options`
+    pub fn command(&self, query: &str, options: &JqOptions) -> Vec<String> {
+        let mut argv = vec![self.exe.display().to_string()];
+        argv.extend(options.to_args());
+        argv.push(query.to_string());
+        argv
+    }
+
+    /// Pull a one line summary out of the error output of the backend, to use as a title
+    pub fn summarize_error(&self, stderr: &str) -> Option<String> {
+        let prefixes: &[&str] = match self.kind {
+            // jq: error: foo/0 is not defined at <top-level>, line 1:
+            // jq: error (at <stdin>:1): Cannot index number with "a"
+            BackendKind::Jq => &["jq: error: ", "jq: error "],
+            // gojq: invalid query: .a | @@
+            // gojq: error: expected an object but got: number (1)
+            BackendKind::Gojq => &["gojq: error: ", "gojq: "],
+            // Error: undefined filter
+            BackendKind::Jaq => &["Error: "],
+        };
+
+        stderr.lines()
+            .find_map(|line| {
+                prefixes.iter()
+                    .find_map(|prefix| line.strip_prefix(prefix))
+            })
+            .map(|summary| summary.trim().trim_end_matches(':').to_string())
+            .filter(|summary| !summary.is_empty())
+    }
+
+    /// Make sense of the error output of the backend: a summary, and where in `query` the problem is (if we can tell)
+    pub fn parse_error(&self, stderr: &str, query: &str) -> Option<QueryError> {
+        let summary = self.summarize_error(stderr)?;
+        let lines: Vec<&str> = query.lines().collect();
+
+        let error = match self.kind {
+            BackendKind::Jq => {
+                let Some(caps) = JQ_LOCATION.captures(&summary) else {
+                    return Some(QueryError { summary, location: None });
+                };
+                let msg = caps["msg"].to_string();
+                let line = caps["line"].parse::<usize>().unwrap_or(1).saturating_sub(1);
+                let text = lines.get(line).copied().unwrap_or("");
+
+                let location = match caps.name("col") {
+                    Some(col) => {
+                        let start = col.as_str().parse::<usize>().unwrap_or(1).saturating_sub(1);
+                        Some(token_at(text, line, start))
+                    }
+                    None => guess_jq_location(&msg, text, line),
+                };
+                let summary = match location {
+                    Some(loc) => format!("{msg} (line {}, column {})", loc.line + 1, loc.start + 1),
+                    None => format!("{msg} (line {})", line + 1),
+                };
+                QueryError { summary, location }
+            }
+            BackendKind::Gojq => {
+                // gojq repeats the offending line, then points at the problem with a caret:
+                //     .a | @@
+                //          ^  unexpected token "@"
+                let mut err_lines = stderr.lines().skip(1);
+                let (Some(echoed), Some(pointer)) = (err_lines.next(), err_lines.next()) else {
+                    return Some(QueryError { summary, location: None });
+                };
+                let Some(detail) = pointer.trim_start().strip_prefix('^') else {
+                    return Some(QueryError { summary, location: None });
+                };
+                let echo_indent = echoed.len() - echoed.trim_start().len();
+                let caret_indent = pointer.len() - pointer.trim_start().len();
+
+                let line = lines.iter()
+                    .position(|l| l.trim_start() == echoed.trim_start())
+                    .unwrap_or(0);
+                let text = lines.get(line).copied().unwrap_or("");
+                let text_indent = text.len() - text.trim_start().len();
+                let start = caret_indent.saturating_sub(echo_indent) + text_indent;
+                let location = token_at(text, line, start);
+                QueryError {
+                    summary: format!("{} (line {}, column {})", detail.trim(), line + 1, start + 1),
+                    location: Some(location),
+                }
+            }
+            BackendKind::Jaq => {
+                let location = JAQ_LOCATION.captures(stderr).map(|caps| {
+                    let line = caps["line"].parse::<usize>().unwrap_or(1).saturating_sub(1);
+                    let start = caps["col"].parse::<usize>().unwrap_or(1).saturating_sub(1);
+                    token_at(lines.get(line).copied().unwrap_or(""), line, start)
+                });
+                QueryError { summary, location }
+            }
+        };
+        Some(error)
+    }
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// The token starting at char column `start` of `text`: a whole word, or a single char
+fn token_at(text: &str, line: usize, start: usize) -> ErrorLocation {
+    let chars: Vec<char> = text.chars().collect();
+    let start = start.min(chars.len().saturating_sub(1));
+    let mut end = start + 1;
+    if chars.get(start).is_some_and(|&ch| is_ident_char(ch)) {
+        while chars.get(end).is_some_and(|&ch| is_ident_char(ch)) {
+            end += 1;
+        }
+    }
+    ErrorLocation { line, start, end }
+}
+
+/// jq (before 1.8) only tells us the line, so make an educated guess at the column from the message
+fn guess_jq_location(msg: &str, text: &str, line: usize) -> Option<ErrorLocation> {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.is_empty() {
+        return None;
+    }
+
+    if msg.contains("unexpected $end") {
+        // the query stopped short, so point at the end of it
+        let last = chars.iter().rposition(|ch| !ch.is_whitespace()).unwrap_or(0);
+        return Some(ErrorLocation { line, start: last, end: last + 1 });
+    }
+
+    if msg.contains("unexpected INVALID_CHARACTER") {
+        let mut in_string = false;
+        let mut prev = ' ';
+        for (i, &ch) in chars.iter().enumerate() {
+            if in_string {
+                if ch == '"' && prev != '\\' {
+                    in_string = false;
+                }
+            } else {
+                let next = chars.get(i + 1).copied().unwrap_or(' ');
+                let invalid = match ch {
+                    '"' => { in_string = true; false }
+                    '@' => !(next.is_ascii_alphabetic() || next == '_'),
+                    '!' => next != '=',
+                    '`' | '~' | '^' | '&' | '\'' | '\\' => true,
+                    _ => false,
+                };
+                if invalid {
+                    return Some(ErrorLocation { line, start: i, end: i + 1 });
+                }
+            }
+            prev = ch;
+        }
+    }
+
+    if let Some(caps) = JQ_NOT_DEFINED.captures(msg) {
+        let name = &caps["name"];
+        let mut search_from = 0;
+        while let Some(found) = text[search_from..].find(name) {
+            let at = search_from + found;
+            let before = text[..at].chars().next_back();
+            let after = text[at + name.len()..].chars().next();
+            // a whole word, and not a field access like `.foo`
+            let whole = !before.is_some_and(|ch| is_ident_char(ch) || ch == '.' || ch == '$')
+                && !after.is_some_and(is_ident_char);
+            if whole {
+                let start = text[..at].chars().count();
+                return Some(ErrorLocation { line, start, end: start + name.chars().count() });
+            }
+            search_from = at + name.len();
+        }
+    }
+
+    // can't tell, blame the whole line
+    let start = chars.iter().position(|ch| !ch.is_whitespace()).unwrap_or(0);
+    let end = chars.iter().rposition(|ch| !ch.is_whitespace()).map(|i| i + 1).unwrap_or(chars.len());
+    Some(ErrorLocation { line, start, end })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backend(kind: BackendKind) -> FilterBackend {
+        FilterBackend {
+            kind,
+            exe: PathBuf::from(kind.exe_name()),
+            version: None,
+            builtins: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn guess_kind_from_path() {
+        assert_eq!(BackendKind::guess_from_path(Path::new("/opt/bin/gojq")), BackendKind::Gojq);
+        assert_eq!(BackendKind::guess_from_path(Path::new("jaq")), BackendKind::Jaq);
+        assert_eq!(BackendKind::guess_from_path(Path::new("/usr/local/bin/jq-1.7")), BackendKind::Jq);
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = BackendConfig::parse(r#"{"backend": "GoJQ", "jq_path": "/opt/bin/gojq"}"#).expect("should parse");
+        assert_eq!(config, BackendConfig {
+            backend: Some(BackendKind::Gojq),
+            jq_path: Some(PathBuf::from("/opt/bin/gojq")),
+        });
+        assert_eq!(BackendConfig::parse("{}").expect("should parse"), BackendConfig::default());
+    }
+
+    #[test]
+    fn parse_config_rejects_unknown_backend() {
+        assert!(BackendConfig::parse(r#"{"backend": "yq"}"#).is_err());
+        assert!(BackendConfig::parse(r#"{"jq_path": 1}"#).is_err());
+        assert!(BackendConfig::parse("[]").is_err());
+    }
+
+    #[test]
+    fn command_puts_query_last() {
+        let options = JqOptions {
+            compact_output: true,
+            ..Default::default()
+        };
+        assert_eq!(backend(BackendKind::Jaq).command(".a", &options), vec!["jaq", "-c", ".a"]);
+    }
+
+    #[test]
+    fn summarize_jq_compile_error() {
+        let stderr = "jq: error: foo/0 is not defined at <top-level>, line 1:\n.a | foo\njq: 1 compile error\n";
+        assert_eq!(
+            backend(BackendKind::Jq).summarize_error(stderr).as_deref(),
+            Some("foo/0 is not defined at <top-level>, line 1")
+        );
+    }
+
+    #[test]
+    fn summarize_jq_runtime_error() {
+        let stderr = "jq: error (at <stdin>:1): Cannot index number with number\n";
+        assert_eq!(
+            backend(BackendKind::Jq).summarize_error(stderr).as_deref(),
+            Some("(at <stdin>:1): Cannot index number with number")
+        );
+    }
+
+    #[test]
+    fn summarize_gojq_error() {
+        let stderr = "gojq: invalid query: .a | @@\n    .a | @@\n         ^  unexpected token \"@\"\n";
+        assert_eq!(
+            backend(BackendKind::Gojq).summarize_error(stderr).as_deref(),
+            Some("invalid query: .a | @@")
+        );
+    }
+
+    #[test]
+    fn parse_jq_error_unexpected_end() {
+        let stderr = "jq: error: syntax error, unexpected $end, expecting ';' or ')' (Unix shell quoting issues?) at <top-level>, line 2:\n foo(1\njq: 1 compile error\n";
+        let error = backend(BackendKind::Jq).parse_error(stderr, ".a |\n foo(1").expect("should parse");
+        assert_eq!(error.summary, "syntax error, unexpected $end, expecting ';' or ')' (line 2, column 6)");
+        assert_eq!(error.location, Some(ErrorLocation { line: 1, start: 5, end: 6 }));
+    }
+
+    #[test]
+    fn parse_jq_error_invalid_character() {
+        let stderr = "jq: error: syntax error, unexpected INVALID_CHARACTER (Unix shell quoting issues?) at <top-level>, line 1:\n.a | \"@@\" | @@\njq: 1 compile error\n";
+        let error = backend(BackendKind::Jq).parse_error(stderr, ".a | \"@@\" | @@").expect("should parse");
+        assert_eq!(error.location, Some(ErrorLocation { line: 0, start: 12, end: 13 }));
+    }
+
+    #[test]
+    fn parse_jq_error_not_defined() {
+        let stderr = "jq: error: foo/0 is not defined at <top-level>, line 1:\n.foo | foo\njq: 1 compile error\n";
+        let error = backend(BackendKind::Jq).parse_error(stderr, ".foo | foo").expect("should parse");
+        assert_eq!(error.summary, "foo/0 is not defined (line 1, column 8)");
+        assert_eq!(error.location, Some(ErrorLocation { line: 0, start: 7, end: 10 }));
+    }
+
+    #[test]
+    fn parse_jq_error_with_column() {
+        let stderr = "jq: error: syntax error, unexpected end at <top-level>, line 1, column 6:\n";
+        let error = backend(BackendKind::Jq).parse_error(stderr, ".a | end").expect("should parse");
+        assert_eq!(error.location, Some(ErrorLocation { line: 0, start: 5, end: 8 }));
+    }
+
+    #[test]
+    fn parse_jq_runtime_error_has_no_location() {
+        let stderr = "jq: error (at <stdin>:1): Cannot index number with number\n";
+        let error = backend(BackendKind::Jq).parse_error(stderr, ".a[0]").expect("should parse");
+        assert_eq!(error.location, None);
+    }
+
+    #[test]
+    fn parse_gojq_error() {
+        let stderr = "gojq: invalid query: .a | @@\n    .a | @@\n         ^  unexpected token \"@\"\n";
+        let error = backend(BackendKind::Gojq).parse_error(stderr, ".a | @@").expect("should parse");
+        assert_eq!(error.summary, "unexpected token \"@\" (line 1, column 6)");
+        assert_eq!(error.location, Some(ErrorLocation { line: 0, start: 5, end: 6 }));
+    }
+
+    #[test]
+    fn summarize_unknown_error() {
+        assert_eq!(backend(BackendKind::Jaq).summarize_error("segfault\n"), None);
+    }
+}
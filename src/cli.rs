@@ -1,14 +1,77 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 
+use crate::{
+    backend::BackendKind,
+    jq::Engine
+};
+
+/// What goes to stdout when the editor exits
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Emit {
+    /// The output of the query
+    Result,
+    /// The query itself
+    Query,
+    /// A shell command that runs the query with the same options and input
+    Command,
+    /// Nothing at all
+    None,
+}
+
+/// Things to do other than editing a query
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print what changed between two json files, path by path, ignoring the order of keys.
+    /// Exits with 1 if there are changes
+    Diff {
+        /// The file as it was
+        old: PathBuf,
+        /// The file as it is now
+        new: PathBuf,
+    },
+}
+
+/// Write a jq query interactively, watching its output as you type
 #[derive(Parser, Debug)]
 #[command(version, about)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     #[arg(short = 'f', long)]
     /// Supply an optional parameter to read the input from a file, instead of stdin
     pub input_filename: Option<PathBuf>,
 
+    #[arg(short = 'i', long, conflicts_with = "input_filename")]
+    /// Edit this file in place: F2 replaces it with the output of the query, after showing what changes
+    pub in_place: Option<PathBuf>,
+
+    #[arg(long, requires = "in_place")]
+    /// When editing in place, keep the original next to it as <file>.bak
+    pub backup: bool,
+
+    #[arg(long, conflicts_with_all = ["query", "from_file"])]
+    /// Start with the query saved under this name (save queries with ctrl-s in the editor)
+    pub saved: Option<String>,
+
+    #[arg(short = 'q', long, conflicts_with = "from_file")]
+    /// Start with this query
+    pub query: Option<String>,
+
+    #[arg(long)]
+    /// Start with the query in this file, like jq's -f. Alt-w writes the query back to it
+    pub from_file: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = Emit::Result)]
+    /// What to print to stdout on exit. The editor itself is drawn on the terminal, so stdout can be piped
+    pub emit: Emit,
+
+    #[arg(long, global = true)]
+    /// When diffing by structure, match up the objects in arrays by their value for this key, instead of by index
+    pub match_key: Option<String>,
+
     #[arg(long, default_value_t = log::LevelFilter::Info)]
     /// The level to log at.
     pub log_level: log::LevelFilter,
@@ -21,11 +84,78 @@ pub struct Cli {
     /// Supply this flag to colorize the json output
     pub colorize: bool,
 
+    #[arg(long)]
+    /// Supply this flag to wrap long lines of output, instead of cutting them off. Toggle it with w in the viewer
+    pub wrap: bool,
+
+    #[arg(long)]
+    /// Supply this flag to re-run the query automatically as you type, instead of waiting for enter
+    pub live: bool,
+
+    #[arg(long, default_value_t = 300)]
+    /// In live mode, how long (in milliseconds) the query must sit unchanged before it is run
+    pub debounce_ms: u64,
+
+    #[arg(long, default_value_t = 10)]
+    /// How long (in seconds) jq may run before it is killed. Use 0 for no limit
+    pub timeout_secs: u64,
+
+    #[arg(long, value_enum)]
+    /// Which jq implementation runs the queries. Guessed from --jq-path if not given, then taken from config.json in the config folder, otherwise jq
+    pub backend: Option<BackendKind>,
+
+    #[arg(long)]
+    /// Path to the executable to run queries with, if it is not the default one on the PATH
+    pub jq_path: Option<PathBuf>,
+
+    #[arg(long, value_enum, default_value_t = Engine::Auto)]
+    /// Whether queries run on the built in evaluator, the backend, or the evaluator with the backend as a fallback
+    pub engine: Engine,
+
+    #[arg(short = 'r', long)]
+    /// Passed to jq: output raw strings, not JSON texts
+    pub raw_output: bool,
+
+    #[arg(short = 's', long)]
+    /// Passed to jq: read the entire input into one large array
+    pub slurp: bool,
+
+    #[arg(short = 'c', long)]
+    /// Passed to jq: compact instead of pretty-printed output
+    pub compact_output: bool,
+
+    #[arg(short = 'S', long)]
+    /// Passed to jq: sort the keys of each object on output
+    pub sort_keys: bool,
+
+    #[arg(short = 'n', long)]
+    /// Passed to jq: use `null` as the single input value
+    pub null_input: bool,
+
+    #[arg(long = "arg", num_args = 2, value_names = ["NAME", "VALUE"])]
+    /// Passed to jq: bind `$NAME` to the string VALUE. May be repeated
+    pub args: Vec<String>,
+
+    #[arg(long = "argjson", num_args = 2, value_names = ["NAME", "JSON"])]
+    /// Passed to jq: bind `$NAME` to the JSON value JSON. May be repeated
+    pub json_args: Vec<String>,
+
+    #[arg(long = "slurpfile", num_args = 2, value_names = ["NAME", "FILE"])]
+    /// Passed to jq: bind `$NAME` to an array of the JSON values in FILE. May be repeated
+    pub slurp_files: Vec<String>,
+
     #[arg(long)]
     /// Testing flag, supply it to use the homegrown json parsing solution rather than delagating to JQ
     pub self_parse_json: bool,
 }
 
+impl Cli {
+    /// The file the input is read from, `None` for stdin
+    pub fn input_path(&self) -> Option<&Path> {
+        self.input_filename.as_deref().or(self.in_place.as_deref())
+    }
+}
+
 fn parse_bool(s: &str) -> Result<bool, &'static str> {
 
     match s.to_lowercase().as_str() {
@@ -0,0 +1,571 @@
+//! Suggestions for the next step of a path, taken from the keys that are actually in the input,
+//! and for the names of builtin functions.
+
+use std::borrow::Cow;
+
+use crate::{
+    builtins::Builtin,
+    eval,
+    jq::JqOptions,
+    json::{self, JsonData, JsonDataType}
+};
+
+/// We stop looking at values after this many, so huge inputs stay responsive
+const MAX_VALUES_EXAMINED: usize = 10_000;
+
+/// A value the path could lead to: borrowed from the input, or computed by a stage of the query
+type Value<'j> = Cow<'j, JsonData<'static>>;
+
+/// One suggestion
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionItem {
+    /// What the popup shows
+    pub label: String,
+    /// The text to put in place of what was typed
+    pub text: String,
+    /// How far back from the end of `text` the cursor goes, e.g. to land inside of `sub()`
+    pub cursor_back: usize,
+}
+
+impl CompletionItem {
+    fn plain(text: String) -> CompletionItem {
+        CompletionItem {
+            label: text.clone(),
+            text,
+            cursor_back: 0,
+        }
+    }
+}
+
+/// The state of the completion popup
+#[derive(Debug)]
+pub struct Completion {
+    pub items: Vec<CompletionItem>,
+    pub selected: usize,
+    /// How many chars before the cursor an accepted item replaces
+    pub replace_len: usize,
+}
+
+impl Completion {
+    pub fn select_next(&mut self) {
+        self.selected = (self.selected + 1) % self.items.len();
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.checked_sub(1).unwrap_or(self.items.len() - 1);
+    }
+
+    pub fn selected_item(&self) -> &CompletionItem {
+        &self.items[self.selected]
+    }
+}
+
+/// One step along a path
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Iterate,
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// True if the key can be written as `.key` instead of `["key"]`
+fn is_plain_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(is_ident_start) && chars.all(is_ident_char)
+}
+
+/// The index just past the string that starts at `start` (which must be a `"`)
+fn skip_string(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' => return Some(i + 1),
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+/// The index of the `"` that opens the string closed by the `"` at `end`
+fn string_start_before(chars: &[char], end: usize) -> Option<usize> {
+    (0..end).rev().find(|&i| {
+        let escapes = chars[..i].iter().rev().take_while(|&&ch| ch == '\\').count();
+        chars[i] == '"' && escapes % 2 == 0
+    })
+}
+
+/// Splits the text before the cursor into what comes before the path being typed, and the path.
+/// For `.a | .items[].na` that is `.a | ` and `.items[].na`. `None` if the cursor is not on a path.
+fn split_path(prefix: &str) -> Option<(&str, &str)> {
+    let chars: Vec<char> = prefix.chars().collect();
+    let mut start = chars.len();
+    // a lone `[` is allowed at the very end, since that is the start of a segment being typed
+    if start > 0 && chars[start - 1] == '[' {
+        start -= 1;
+    }
+    while let Some(&ch) = start.checked_sub(1).and_then(|i| chars.get(i)) {
+        match ch {
+            '.' | '?' => start -= 1,
+            ch if is_ident_char(ch) => start -= 1,
+            ']' => {
+                // `[]`, `[0]` or `["key"]`
+                let mut i = start - 1;
+                if i > 0 && chars[i - 1] == '"' {
+                    let Some(open) = string_start_before(&chars, i - 1) else { break; };
+                    i = open;
+                }
+                let Some(open) = (0..i).rev().find(|&j| chars[j] == '[') else { break; };
+                if !chars[open + 1..i].iter().all(|ch| ch.is_ascii_digit() || *ch == '-' || *ch == '"') {
+                    break;
+                }
+                start = open;
+            }
+            '"' if start >= 2 => {
+                // `."key"`
+                let Some(open) = string_start_before(&chars, start - 1) else { break; };
+                if open == 0 || chars[open - 1] != '.' {
+                    break;
+                }
+                start = open;
+            }
+            _ => break,
+        }
+    }
+    // the path has to start with a dot, otherwise it is a function name or a variable
+    while start < chars.len() && chars[start] != '.' {
+        if !is_ident_char(chars[start]) {
+            break;
+        }
+        start += 1;
+    }
+    let byte_start: usize = chars[..start].iter().map(|ch| ch.len_utf8()).sum();
+    let (context, path) = prefix.split_at(byte_start);
+    if context.ends_with('$') || context.ends_with(is_ident_char) {
+        // `$x.foo`, `foo.bar` or just `length`, not paths into the input
+        return None;
+    }
+    Some((context, path))
+}
+
+/// Splits a path into the complete segments and the part still being typed
+fn parse_path(path: &str) -> Option<(Vec<Segment>, &str)> {
+    let chars: Vec<char> = path.chars().collect();
+    let byte_at = |i: usize| -> usize { chars[..i].iter().map(|ch| ch.len_utf8()).sum() };
+
+    let mut segments = Vec::new();
+    let mut i = 0;
+    // where the segment we are looking at started
+    let mut partial_start;
+    loop {
+        partial_start = i;
+        while chars.get(i) == Some(&'?') {
+            i += 1;
+            partial_start = i;
+        }
+        if i >= chars.len() {
+            break;
+        }
+        let dotted = chars[i] == '.';
+        let mut j = if dotted { i + 1 } else { i };
+        match chars.get(j) {
+            Some(&ch) if dotted && is_ident_start(ch) => {
+                let end = (j..chars.len()).find(|&k| !is_ident_char(chars[k])).unwrap_or(chars.len());
+                if end == chars.len() {
+                    // still typing the name
+                    break;
+                }
+                segments.push(Segment::Key(chars[j..end].iter().collect()));
+                i = end;
+            }
+            Some('"') if dotted => {
+                let end = skip_string(&chars, j)?;
+                let lex: String = chars[j..end].iter().collect();
+                segments.push(Segment::Key(json::unescape(&lex).into_owned()));
+                i = end;
+            }
+            Some('[') => {
+                j += 1;
+                let close = match chars.get(j) {
+                    Some('"') => skip_string(&chars, j)?,
+                    _ => (j..chars.len()).find(|&k| chars[k] == ']').unwrap_or(chars.len()),
+                };
+                if chars.get(close) != Some(&']') {
+                    // still typing what goes in the brackets
+                    break;
+                }
+                let inside: String = chars[j..close].iter().collect();
+                let segment = if inside.is_empty() {
+                    Segment::Iterate
+                } else if inside.starts_with('"') {
+                    Segment::Key(json::unescape(&inside).into_owned())
+                } else {
+                    Segment::Index(inside.parse().ok()?)
+                };
+                segments.push(segment);
+                i = close + 1;
+            }
+            None if dotted => break,
+            _ => return None,
+        }
+    }
+    Some((segments, &path[byte_at(partial_start)..]))
+}
+
+/// Follows one step of a path from each of the values
+fn follow<'j>(values: Vec<Value<'j>>, segment: &Segment) -> Vec<Value<'j>> {
+    let mut out = Vec::new();
+    for value in values {
+        if out.len() >= MAX_VALUES_EXAMINED {
+            break;
+        }
+        match value {
+            Cow::Borrowed(value) => out.extend(step(value, segment).into_iter().map(Cow::Borrowed)),
+            Cow::Owned(value) => out.extend(step(&value, segment).into_iter().cloned().map(Cow::Owned)),
+        }
+    }
+    out
+}
+
+/// The values one step of a path leads to from `value`
+fn step<'v>(value: &'v JsonData<'static>, segment: &Segment) -> Vec<&'v JsonData<'static>> {
+    match (segment, value.ty()) {
+        (Segment::Key(name), JsonDataType::Object { entries }) => entries.iter()
+            .filter(|(key, _)| key.name() == name.as_str())
+            .map(|(_, value)| value)
+            .collect(),
+        (Segment::Index(idx), JsonDataType::Array { elems }) => {
+            let idx = if *idx < 0 { elems.len() as i64 + idx } else { *idx };
+            usize::try_from(idx).ok()
+                .and_then(|idx| elems.get(idx))
+                .into_iter()
+                .collect()
+        }
+        (Segment::Iterate, JsonDataType::Array { elems }) => elems.iter().collect(),
+        (Segment::Iterate, JsonDataType::Object { entries }) => entries.iter().map(|(_, value)| value).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// An open bracket (or the whole query) that the cursor is inside of
+struct Group {
+    /// Where its contents start
+    start: usize,
+    /// The last pipe directly inside of it
+    last_pipe: Option<usize>,
+    /// True for `map(`, whose contents see each element rather than the whole input
+    iterates: bool,
+}
+
+/// Works out the values that the path being typed will be applied to,
+/// by running the pipeline stages that lead up to it
+fn input_values<'j>(context: &str, root: Value<'j>) -> Option<Vec<Value<'j>>> {
+    let chars: Vec<(usize, char)> = context.char_indices().collect();
+    let just_chars: Vec<char> = context.chars().collect();
+    let mut groups = vec![Group { start: 0, last_pipe: None, iterates: false }];
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (at, ch) = chars[i];
+        let next = chars.get(i + 1).map(|&(_, ch)| ch);
+        match ch {
+            '"' => {
+                i = skip_string(&just_chars, i).unwrap_or(chars.len());
+                continue;
+            }
+            '#' => {
+                while i < chars.len() && chars[i].1 != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '(' | '[' | '{' => {
+                let iterates = ch == '(' && context[..at].trim_end().ends_with("map");
+                groups.push(Group { start: at + 1, last_pipe: None, iterates });
+            }
+            ')' | ']' | '}' if groups.len() > 1 => {
+                groups.pop();
+            }
+            '|' if next == Some('=') => i += 1,
+            '|' => {
+                if let Some(group) = groups.last_mut() {
+                    group.last_pipe = Some(at);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+
+    let mut values = vec![root];
+    for group in &groups {
+        if group.iterates {
+            values = follow(values, &Segment::Iterate);
+        }
+        if let Some(pipe) = group.last_pipe {
+            let stage = &context[group.start..pipe];
+            let mut outputs = Vec::new();
+            for value in values.iter().take(MAX_VALUES_EXAMINED) {
+                let result = match value {
+                    Cow::Borrowed(value) => eval::values(stage, value),
+                    Cow::Owned(value) => eval::values(stage, value)
+                        .map(|out| out.into_iter().map(|v| Cow::Owned(v.into_owned())).collect()),
+                };
+                match result {
+                    Ok(out) => outputs.extend(out),
+                    Err(e) => {
+                        log::info!("can not work out the input to complete against: {e}");
+                        return None;
+                    }
+                }
+            }
+            values = outputs;
+        }
+    }
+    Some(values)
+}
+
+/// The ways to write the next step from the values, given what has been typed of it so far.
+/// jq only takes a dot in front of brackets at the start of a path, `.[]` but `.a[]`, so after
+/// another segment the brackets replace the dot
+fn next_segments(values: &[Value], partial: &str, after_segment: bool) -> Vec<String> {
+    let dot = if partial.starts_with('.') && !after_segment { "." } else { "" };
+    let bracketed = partial.trim_start_matches('.').starts_with('[');
+
+    let mut items: Vec<String> = Vec::new();
+    let mut push = |item: String| {
+        let typed = if item.starts_with('.') { partial } else { partial.trim_start_matches('.') };
+        if item.starts_with(typed) && !items.contains(&item) {
+            items.push(item);
+        }
+    };
+    for value in values.iter().take(MAX_VALUES_EXAMINED) {
+        match value.ty() {
+            JsonDataType::Object { entries } => {
+                for (key, _) in entries {
+                    let name = key.name();
+                    if is_plain_key(&name) && !bracketed {
+                        push(format!(".{name}"));
+                    } else {
+                        push(format!("{dot}[{}]", json::quote(&name)));
+                    }
+                }
+            }
+            JsonDataType::Array { .. } => push(format!("{dot}[]")),
+            _ => {}
+        }
+    }
+    items
+}
+
+/// The completions for the path that ends at the cursor. `prefix` is the query up to the cursor
+pub fn complete_path(prefix: &str, input: &JsonData<'static>, options: &JqOptions) -> Option<Completion> {
+    let (context, path) = split_path(prefix)?;
+    let (segments, partial) = parse_path(path)?;
+
+    // with -s, the input has already been slurped into an array
+    let root = match options.null_input {
+        true => Cow::Owned(JsonData::null()),
+        false => Cow::Borrowed(input),
+    };
+    let mut values = input_values(context, root)?;
+    for segment in &segments {
+        values = follow(values, segment);
+    }
+
+    let items: Vec<CompletionItem> = next_segments(&values, partial, !segments.is_empty())
+        .into_iter()
+        .map(CompletionItem::plain)
+        .collect();
+    if items.is_empty() {
+        return None;
+    }
+    Some(Completion {
+        items,
+        selected: 0,
+        replace_len: partial.chars().count(),
+    })
+}
+
+/// True if the text ends inside of a string literal
+fn ends_in_string(prefix: &str) -> bool {
+    let chars: Vec<char> = prefix.chars().collect();
+    let mut in_string = false;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if in_string => i += 1,
+            '"' => in_string = !in_string,
+            _ => {}
+        }
+        i += 1;
+    }
+    in_string
+}
+
+/// The name being typed just before the cursor, if it could be a function call
+pub fn word_before(prefix: &str) -> Option<&str> {
+    if ends_in_string(prefix) {
+        return None;
+    }
+    let start = prefix.trim_end_matches(is_ident_char).len();
+    let word = &prefix[start..];
+    let preceded_by = prefix[..start].chars().next_back();
+    if word.is_empty() || !word.starts_with(is_ident_start) || matches!(preceded_by, Some('.' | '$')) {
+        return None;
+    }
+    Some(word)
+}
+
+/// The builtins that start with the name being typed. Accepting one fills in the name,
+/// with parentheses for the arguments (and the cursor between them) if it takes any
+pub fn complete_builtin(prefix: &str, builtins: &[Builtin]) -> Option<Completion> {
+    let word = word_before(prefix)?;
+    let items: Vec<CompletionItem> = builtins.iter()
+        .filter(|builtin| builtin.name.starts_with(word))
+        .map(|builtin| {
+            let (text, cursor_back) = match builtin.arity {
+                0 => (builtin.name.clone(), 0),
+                _ => (format!("{}()", builtin.name), 1),
+            };
+            CompletionItem {
+                label: builtin.signature(),
+                text,
+                cursor_back,
+            }
+        })
+        .collect();
+    if items.is_empty() {
+        return None;
+    }
+    Some(Completion {
+        items,
+        selected: 0,
+        replace_len: word.chars().count(),
+    })
+}
+
+/// The name of the function whose arguments the cursor is in, so its signatures can be shown.
+/// For `sub("a"; "b` that is `sub`
+pub fn enclosing_call(prefix: &str) -> Option<&str> {
+    // walk back to the innermost open paren, and take the name in front of it
+    let mut depth = 0usize;
+    let mut in_string = ends_in_string(prefix);
+    for (at, ch) in prefix.char_indices().rev() {
+        match ch {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            ')' | ']' | '}' => depth += 1,
+            '[' | '{' if depth == 0 => return None,
+            '(' if depth == 0 => return word_before(&prefix[..at]),
+            '(' | '[' | '{' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn complete(prefix: &str, source: &'static str) -> Vec<String> {
+        let input = json::loads(source).expect("test input should parse");
+        complete_path(prefix, &input, &JqOptions::default())
+            .map(|completion| completion.items.into_iter().map(|item| item.text).collect())
+            .unwrap_or_default()
+    }
+
+    const SOURCE: &str = r#"{"items": [{"id": 1, "name": "a"}, {"id": 2, "weird key": true}], "count": 2}"#;
+
+    #[test]
+    fn split_paths() {
+        assert_eq!(split_path(".a | .items[].na"), Some((".a | ", ".items[].na")));
+        assert_eq!(split_path(r#"map(.["x y"].b"#), Some(("map(", r#".["x y"].b"#)));
+        assert_eq!(split_path(r#"."x y".b"#), Some(("", r#"."x y".b"#)));
+        assert_eq!(split_path("$x.foo"), None);
+        assert_eq!(split_path("length"), None);
+    }
+
+    #[test]
+    fn parse_paths() {
+        assert_eq!(parse_path(".items[].na"), Some((vec![Segment::Key("items".into()), Segment::Iterate], ".na")));
+        assert_eq!(parse_path(r#".["x y"][0]."#), Some((vec![Segment::Key("x y".into()), Segment::Index(0)], ".")));
+        assert_eq!(parse_path(".a["), Some((vec![Segment::Key("a".into())], "[")));
+        assert_eq!(parse_path(""), Some((vec![], "")));
+    }
+
+    #[test]
+    fn completes_top_level_keys() {
+        assert_eq!(complete("", SOURCE), vec![".items", ".count"]);
+        assert_eq!(complete(".", SOURCE), vec![".items", ".count"]);
+        assert_eq!(complete(".it", SOURCE), vec![".items"]);
+    }
+
+    #[test]
+    fn completes_through_arrays() {
+        assert_eq!(complete(".items", SOURCE), vec![".items"]);
+        assert_eq!(complete(".items.", SOURCE), vec!["[]"]);
+        assert_eq!(complete(".items[]", SOURCE), vec![".id", ".name", r#"["weird key"]"#]);
+        assert_eq!(complete(".items[1].", SOURCE), vec![".id", r#"["weird key"]"#]);
+        assert_eq!(complete(".items.[", SOURCE), vec!["[]"]);
+        assert_eq!(complete(".items[", SOURCE), vec!["[]"]);
+        assert_eq!(complete(".items[0][", SOURCE), vec![r#"["id"]"#, r#"["name"]"#]);
+    }
+
+    #[test]
+    fn completes_arrays() {
+        let source = r#"{"a": [[1], [2]]}"#;
+        assert_eq!(complete(".a", source), vec![".a"]);
+        assert_eq!(complete(".a.", source), vec!["[]"]);
+        assert_eq!(complete(".", source), vec![".a"]);
+        assert_eq!(complete(".", "[1]"), vec![".[]"]);
+        assert_eq!(complete(".a[]", source), vec!["[]"]);
+    }
+
+    #[test]
+    fn completes_after_pipes_and_map() {
+        assert_eq!(complete(".items | .[0] | .", SOURCE), vec![".id", ".name"]);
+        assert_eq!(complete(".items | map(.n", SOURCE), vec![".name"]);
+        assert_eq!(complete(".items[] | select(.id == 1) | .", SOURCE), vec![".id", ".name"]);
+    }
+
+    #[test]
+    fn completes_builtins() {
+        let builtins = crate::builtins::bundled();
+        let completion = complete_builtin(".a | to_en", &builtins).expect("has completions");
+        assert_eq!(completion.replace_len, 5);
+        assert_eq!(completion.items, vec![CompletionItem {
+            label: "to_entries/0".to_string(),
+            text: "to_entries".to_string(),
+            cursor_back: 0,
+        }]);
+
+        let completion = complete_builtin("map(su", &builtins).expect("has completions");
+        let labels: Vec<&str> = completion.items.iter().map(|item| item.label.as_str()).collect();
+        assert_eq!(labels, vec!["sub/2", "sub/3"]);
+        assert_eq!(completion.items[0].text, "sub()");
+        assert_eq!(completion.items[0].cursor_back, 1);
+
+        assert!(complete_builtin(".to_en", &builtins).is_none());
+        assert!(complete_builtin("$to_en", &builtins).is_none());
+    }
+
+    #[test]
+    fn finds_the_function_being_called() {
+        assert_eq!(enclosing_call(r#"sub("a"; "b"#), Some("sub"));
+        assert_eq!(enclosing_call(r#"sub("(a"; "b"#), Some("sub"));
+        assert_eq!(enclosing_call("map(select(.a) | .b"), Some("map"));
+        assert_eq!(enclosing_call("map(sub(x"), Some("sub"));
+        assert_eq!(enclosing_call("[.a, .b"), None);
+        assert_eq!(enclosing_call(".a"), None);
+    }
+}
@@ -0,0 +1,149 @@
+//! Line based diffs between two texts.
+
+/// Above this many cells in the comparison table, we give up on a fine grained diff
+/// and show the changed region as removed and then added
+const MAX_TABLE_SIZE: usize = 16_000_000;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DiffLine<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+impl<'a> DiffLine<'a> {
+    pub fn is_change(&self) -> bool {
+        !matches!(self, DiffLine::Same(_))
+    }
+}
+
+/// The lines of `old` and `new` in order, marked by whether they were kept, removed or added
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<DiffLine<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    // the common start and end are easy, and usually most of it
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut out: Vec<DiffLine> = old[..prefix].iter().map(|line| DiffLine::Same(line)).collect();
+    out.extend(diff_middle(old_mid, new_mid));
+    out.extend(old[old.len() - suffix..].iter().map(|line| DiffLine::Same(line)));
+    out
+}
+
+/// A longest common subsequence diff of the lines
+fn diff_middle<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let (n, m) = (old.len(), new.len());
+    if (n + 1) * (m + 1) > MAX_TABLE_SIZE {
+        log::info!("diff of {n} and {m} lines is too big to do line by line");
+        return old.iter().map(|line| DiffLine::Removed(line))
+            .chain(new.iter().map(|line| DiffLine::Added(line)))
+            .collect();
+    }
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let width = m + 1;
+    let mut lcs = vec![0u32; (n + 1) * width];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * width + j] = if old[i] == new[j] {
+                lcs[(i + 1) * width + j + 1] + 1
+            } else {
+                lcs[(i + 1) * width + j].max(lcs[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut out = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n && j < m {
+        if old[i] == new[j] {
+            out.push(DiffLine::Same(old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[(i + 1) * width + j] >= lcs[i * width + j + 1] {
+            out.push(DiffLine::Removed(old[i]));
+            i += 1;
+        } else {
+            out.push(DiffLine::Added(new[j]));
+            j += 1;
+        }
+    }
+    out.extend(old[i..].iter().map(|line| DiffLine::Removed(line)));
+    out.extend(new[j..].iter().map(|line| DiffLine::Added(line)));
+    out
+}
+
+/// Only the changes and `context` lines around them. `None` stands for the lines left out
+pub fn with_context<'a>(diff: &[DiffLine<'a>], context: usize) -> Vec<Option<DiffLine<'a>>> {
+    let near_change = |i: usize| {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(diff.len());
+        diff[start..end].iter().any(DiffLine::is_change)
+    };
+
+    let mut out = Vec::new();
+    let mut skipping = false;
+    for (i, line) in diff.iter().enumerate() {
+        if near_change(i) {
+            out.push(Some(*line));
+            skipping = false;
+        } else if !skipping {
+            out.push(None);
+            skipping = true;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn identical() {
+        assert_eq!(diff_lines("a\nb", "a\nb"), vec![DiffLine::Same("a"), DiffLine::Same("b")]);
+    }
+
+    #[test]
+    fn changes_in_the_middle() {
+        assert_eq!(diff_lines("a\nb\nc\nd", "a\nx\nc\nd\ne"), vec![
+            DiffLine::Same("a"),
+            DiffLine::Removed("b"),
+            DiffLine::Added("x"),
+            DiffLine::Same("c"),
+            DiffLine::Same("d"),
+            DiffLine::Added("e"),
+        ]);
+    }
+
+    #[test]
+    fn keeps_the_longest_common_lines() {
+        assert_eq!(diff_lines("x\na\nb\ny", "a\nq\nb"), vec![
+            DiffLine::Removed("x"),
+            DiffLine::Same("a"),
+            DiffLine::Added("q"),
+            DiffLine::Same("b"),
+            DiffLine::Removed("y"),
+        ]);
+    }
+
+    #[test]
+    fn context_around_changes() {
+        let diff = diff_lines("1\n2\n3\n4\n5\n6\n7", "1\n2\n3\nX\n5\n6\n7");
+        assert_eq!(with_context(&diff, 1), vec![
+            None,
+            Some(DiffLine::Same("3")),
+            Some(DiffLine::Removed("4")),
+            Some(DiffLine::Added("X")),
+            Some(DiffLine::Same("5")),
+            None,
+        ]);
+    }
+}
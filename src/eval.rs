@@ -0,0 +1,892 @@
+//! A small, in-process evaluator for the most commonly used subset of jq.
+//!
+//! It works directly on the tree from `json::loads_all`, which saves a process spawn and a re-parse
+//! of the input on every run. Anything it does not understand is an error, and the caller is
+//! expected to hand the query to the real jq instead.
+//!
+//! Values are borrowed from the input wherever they can be, so that `.` or `.[]` on a large input
+//! does not copy it. Only what the query computes is owned.
+
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    sync::atomic::{AtomicBool, Ordering as AtomicOrdering}
+};
+
+use anyhow::{
+    anyhow, bail, Result
+};
+
+use crate::{
+    jq::JqOptions,
+    json::{
+        self, DumpOptions, JsonData, JsonDataType, JsonKey
+    }
+};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Dot,
+    /// `.foo`
+    Field(String),
+    Ident(String),
+    Str(String),
+    Num(f64),
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    OpenBrace,
+    CloseBrace,
+    Pipe,
+    Comma,
+    Colon,
+    Minus,
+    Cmp(CmpOp),
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Identity,
+    Literal(JsonData<'static>),
+    /// `target.name`
+    Field(Box<Expr>, String),
+    /// `target[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `target[]`
+    Iterate(Box<Expr>),
+    Pipe(Box<Expr>, Box<Expr>),
+    Comma(Box<Expr>, Box<Expr>),
+    Compare(CmpOp, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    /// `[...]`, `None` for `[]`
+    Array(Option<Box<Expr>>),
+    Object(Vec<(Expr, Expr)>),
+    Select(Box<Expr>),
+    Map(Box<Expr>),
+    Keys,
+    Length,
+    Not,
+    Empty,
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+fn lex(query: &str) -> Result<Vec<Lexeme>> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut lexemes = Vec::new();
+    let mut i = 0;
+
+    let take_while = |i: &mut usize, pred: fn(char) -> bool| -> String {
+        let start = *i;
+        while *i < chars.len() && pred(chars[*i]) {
+            *i += 1;
+        }
+        chars[start..*i].iter().collect()
+    };
+
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+        let lexeme = match ch {
+            ch if ch.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '.' if next.is_some_and(is_ident_start) => {
+                i += 1;
+                Lexeme::Field(take_while(&mut i, is_ident_char))
+            }
+            '.' if next == Some('.') => bail!("`..` is not supported natively"),
+            '.' if next.is_some_and(|n| n.is_ascii_digit()) => {
+                Lexeme::Num(take_while(&mut i, |c| c.is_ascii_digit() || c == '.').parse()?)
+            }
+            '.' => { i += 1; Lexeme::Dot }
+            ch if ch.is_ascii_digit() => {
+                let num = take_while(&mut i, |c| c.is_ascii_digit() || c == '.');
+                if chars.get(i).is_some_and(|c| matches!(c, 'e' | 'E')) {
+                    bail!("exponents are not supported natively");
+                }
+                Lexeme::Num(num.parse()?)
+            }
+            ch if is_ident_start(ch) => Lexeme::Ident(take_while(&mut i, is_ident_char)),
+            '"' => {
+                // find the closing quote, skipping over escapes
+                let start = i;
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\\' {
+                        if chars.get(i + 1) == Some(&'(') {
+                            bail!("string interpolation is not supported natively");
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    bail!("unterminated string");
+                }
+                i += 1;
+                let lex: String = chars[start..i].iter().collect();
+                Lexeme::Str(json::unescape(&lex).into_owned())
+            }
+            '(' => { i += 1; Lexeme::OpenParen }
+            ')' => { i += 1; Lexeme::CloseParen }
+            '[' => { i += 1; Lexeme::OpenBracket }
+            ']' => { i += 1; Lexeme::CloseBracket }
+            '{' => { i += 1; Lexeme::OpenBrace }
+            '}' => { i += 1; Lexeme::CloseBrace }
+            '|' if next == Some('=') => bail!("`|=` is not supported natively"),
+            '|' => { i += 1; Lexeme::Pipe }
+            ',' => { i += 1; Lexeme::Comma }
+            ':' => { i += 1; Lexeme::Colon }
+            '-' => { i += 1; Lexeme::Minus }
+            '=' if next == Some('=') => { i += 2; Lexeme::Cmp(CmpOp::Eq) }
+            '!' if next == Some('=') => { i += 2; Lexeme::Cmp(CmpOp::Ne) }
+            '<' if next == Some('=') => { i += 2; Lexeme::Cmp(CmpOp::Le) }
+            '>' if next == Some('=') => { i += 2; Lexeme::Cmp(CmpOp::Ge) }
+            '<' => { i += 1; Lexeme::Cmp(CmpOp::Lt) }
+            '>' => { i += 1; Lexeme::Cmp(CmpOp::Gt) }
+            other => bail!("`{other}` is not supported natively"),
+        };
+        lexemes.push(lexeme);
+    }
+
+    Ok(lexemes)
+}
+
+struct Parser {
+    lexemes: Vec<Lexeme>,
+    idx: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.idx)
+    }
+    fn peek_at(&self, offset: usize) -> Option<&Lexeme> {
+        self.lexemes.get(self.idx + offset)
+    }
+    fn next(&mut self) -> Option<Lexeme> {
+        let lexeme = self.lexemes.get(self.idx).cloned();
+        self.idx += 1;
+        lexeme
+    }
+    fn eat(&mut self, lexeme: &Lexeme) -> bool {
+        if self.peek() == Some(lexeme) {
+            self.idx += 1;
+            true
+        } else {
+            false
+        }
+    }
+    fn expect(&mut self, lexeme: Lexeme) -> Result<()> {
+        match self.next() {
+            Some(got) if got == lexeme => Ok(()),
+            got => bail!("expected {lexeme:?}, got {got:?}"),
+        }
+    }
+
+    /// `a | b`, the loosest binding operator
+    fn parse_pipe(&mut self) -> Result<Expr> {
+        let lhs = self.parse_comma()?;
+        if self.eat(&Lexeme::Pipe) {
+            let rhs = self.parse_pipe()?;
+            return Ok(Expr::Pipe(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+    fn parse_comma(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_or()?;
+        while self.eat(&Lexeme::Comma) {
+            let rhs = self.parse_or()?;
+            lhs = Expr::Comma(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn parse_or(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_and()?;
+        while self.eat(&Lexeme::Ident("or".to_string())) {
+            let rhs = self.parse_and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn parse_and(&mut self) -> Result<Expr> {
+        let mut lhs = self.parse_compare()?;
+        while self.eat(&Lexeme::Ident("and".to_string())) {
+            let rhs = self.parse_compare()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+    fn parse_compare(&mut self) -> Result<Expr> {
+        let lhs = self.parse_postfix()?;
+        if let Some(Lexeme::Cmp(op)) = self.peek() {
+            let op = *op;
+            self.idx += 1;
+            let rhs = self.parse_postfix()?;
+            return Ok(Expr::Compare(op, Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+    fn parse_postfix(&mut self) -> Result<Expr> {
+        let mut expr = self.parse_term()?;
+        loop {
+            match (self.peek(), self.peek_at(1)) {
+                (Some(Lexeme::Field(name)), _) => {
+                    let name = name.clone();
+                    self.idx += 1;
+                    expr = Expr::Field(Box::new(expr), name);
+                }
+                (Some(Lexeme::Dot), Some(Lexeme::Str(name))) => {
+                    let name = name.clone();
+                    self.idx += 2;
+                    expr = Expr::Field(Box::new(expr), name);
+                }
+                (Some(Lexeme::Dot), Some(Lexeme::OpenBracket)) => {
+                    self.idx += 1;
+                    expr = self.parse_brackets(expr)?;
+                }
+                (Some(Lexeme::OpenBracket), _) => {
+                    expr = self.parse_brackets(expr)?;
+                }
+                _ => break,
+            }
+        }
+        Ok(expr)
+    }
+    /// `target[]` or `target[index]`, starting at the `[`
+    fn parse_brackets(&mut self, target: Expr) -> Result<Expr> {
+        self.expect(Lexeme::OpenBracket)?;
+        if self.eat(&Lexeme::CloseBracket) {
+            return Ok(Expr::Iterate(Box::new(target)));
+        }
+        let index = self.parse_pipe()?;
+        self.expect(Lexeme::CloseBracket)?;
+        Ok(Expr::Index(Box::new(target), Box::new(index)))
+    }
+    fn parse_term(&mut self) -> Result<Expr> {
+        let Some(lexeme) = self.next() else {
+            bail!("unexpected end of query");
+        };
+        let expr = match lexeme {
+            // `.[` and `."foo"` are handled as postfix on the identity
+            Lexeme::Dot => match self.peek() {
+                Some(Lexeme::OpenBracket) => self.parse_brackets(Expr::Identity)?,
+                Some(Lexeme::Str(name)) => {
+                    let name = name.clone();
+                    self.idx += 1;
+                    Expr::Field(Box::new(Expr::Identity), name)
+                }
+                _ => Expr::Identity,
+            },
+            Lexeme::Field(name) => Expr::Field(Box::new(Expr::Identity), name),
+            Lexeme::Num(n) => Expr::Literal(JsonData::number(n)),
+            Lexeme::Minus => match self.next() {
+                Some(Lexeme::Num(n)) => Expr::Literal(JsonData::number(-n)),
+                other => bail!("negation of {other:?} is not supported natively"),
+            },
+            Lexeme::Str(s) => Expr::Literal(JsonData::string(&s)),
+            Lexeme::OpenParen => {
+                let inner = self.parse_pipe()?;
+                self.expect(Lexeme::CloseParen)?;
+                inner
+            }
+            Lexeme::OpenBracket => {
+                if self.eat(&Lexeme::CloseBracket) {
+                    Expr::Array(None)
+                } else {
+                    let inner = self.parse_pipe()?;
+                    self.expect(Lexeme::CloseBracket)?;
+                    Expr::Array(Some(Box::new(inner)))
+                }
+            }
+            Lexeme::OpenBrace => self.parse_object()?,
+            Lexeme::Ident(name) => match name.as_str() {
+                "true" => Expr::Literal(JsonData::boolean(true)),
+                "false" => Expr::Literal(JsonData::boolean(false)),
+                "null" => Expr::Literal(JsonData::null()),
+                "keys" => Expr::Keys,
+                "length" => Expr::Length,
+                "not" => Expr::Not,
+                "empty" => Expr::Empty,
+                "select" => Expr::Select(Box::new(self.parse_argument()?)),
+                "map" => Expr::Map(Box::new(self.parse_argument()?)),
+                other => bail!("`{other}` is not supported natively"),
+            },
+            other => bail!("unexpected {other:?}"),
+        };
+        Ok(expr)
+    }
+    /// The single argument of a builtin like `select(f)`
+    fn parse_argument(&mut self) -> Result<Expr> {
+        self.expect(Lexeme::OpenParen)?;
+        let arg = self.parse_pipe()?;
+        self.expect(Lexeme::CloseParen)?;
+        Ok(arg)
+    }
+    /// `{a, "b": .c, (.d): .e}`, after the `{`
+    fn parse_object(&mut self) -> Result<Expr> {
+        let mut entries = Vec::new();
+        if self.eat(&Lexeme::CloseBrace) {
+            return Ok(Expr::Object(entries));
+        }
+        loop {
+            let (key, shorthand) = match self.next() {
+                Some(Lexeme::Ident(name)) | Some(Lexeme::Str(name)) => {
+                    let shorthand = Expr::Field(Box::new(Expr::Identity), name.clone());
+                    (Expr::Literal(JsonData::string(&name)), Some(shorthand))
+                }
+                Some(Lexeme::OpenParen) => {
+                    let key = self.parse_pipe()?;
+                    self.expect(Lexeme::CloseParen)?;
+                    (key, None)
+                }
+                other => bail!("unexpected {other:?} as an object key"),
+            };
+            let value = if self.eat(&Lexeme::Colon) {
+                self.parse_object_value()?
+            } else {
+                shorthand.ok_or_else(|| anyhow!("expected `:` after a computed object key"))?
+            };
+            entries.push((key, value));
+
+            match self.next() {
+                Some(Lexeme::Comma) => continue,
+                Some(Lexeme::CloseBrace) => break,
+                other => bail!("unexpected {other:?} in object"),
+            }
+        }
+        Ok(Expr::Object(entries))
+    }
+    /// Object values may only be pipes of terms, like `{a: .b | .c}`, so that commas separate entries
+    fn parse_object_value(&mut self) -> Result<Expr> {
+        let lhs = self.parse_postfix()?;
+        if self.eat(&Lexeme::Pipe) {
+            let rhs = self.parse_object_value()?;
+            return Ok(Expr::Pipe(Box::new(lhs), Box::new(rhs)));
+        }
+        Ok(lhs)
+    }
+}
+
+fn parse(query: &str) -> Result<Expr> {
+    let lexemes = lex(query)?;
+    if lexemes.is_empty() {
+        // jq treats the empty program as `.`
+        return Ok(Expr::Identity);
+    }
+    let mut parser = Parser { lexemes, idx: 0 };
+    let expr = parser.parse_pipe()?;
+    if let Some(rest) = parser.peek() {
+        bail!("unexpected {rest:?}");
+    }
+    Ok(expr)
+}
+
+/// The order jq sorts values in: null < false < true < numbers < strings < arrays < objects
+fn type_rank(json: &JsonData) -> u8 {
+    match json.ty() {
+        JsonDataType::Null => 0,
+        JsonDataType::Boolean { .. } => if json.is_truthy() { 2 } else { 1 },
+        JsonDataType::Number { .. } => 3,
+        JsonDataType::Str { .. } => 4,
+        JsonDataType::Array { .. } => 5,
+        JsonDataType::Object { .. } => 6,
+    }
+}
+
+fn sorted_entries<'j, 'a>(entries: &'j [(JsonKey<'a>, JsonData<'a>)]) -> Vec<(String, &'j JsonData<'a>)> {
+    let mut sorted: Vec<_> = entries.iter()
+        .map(|(key, value)| (key.name().into_owned(), value))
+        .collect();
+    sorted.sort_by(|(a, _), (b, _)| a.cmp(b));
+    sorted
+}
+
+fn compare(a: &JsonData, b: &JsonData) -> Ordering {
+    let by_type = type_rank(a).cmp(&type_rank(b));
+    if by_type != Ordering::Equal {
+        return by_type;
+    }
+    match (a.ty(), b.ty()) {
+        (JsonDataType::Number { .. }, JsonDataType::Number { .. }) => {
+            let (x, y) = (a.as_f64().unwrap_or(f64::NAN), b.as_f64().unwrap_or(f64::NAN));
+            x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+        }
+        (JsonDataType::Str { .. }, JsonDataType::Str { .. }) => a.as_str().cmp(&b.as_str()),
+        (JsonDataType::Array { elems: xs }, JsonDataType::Array { elems: ys }) => {
+            for (x, y) in xs.iter().zip(ys.iter()) {
+                let ord = compare(x, y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            xs.len().cmp(&ys.len())
+        }
+        (JsonDataType::Object { entries: xs }, JsonDataType::Object { entries: ys }) => {
+            // first by the sorted sets of keys, then value by value
+            let (xs, ys) = (sorted_entries(xs), sorted_entries(ys));
+            let keys = xs.iter().map(|(k, _)| k).cmp(ys.iter().map(|(k, _)| k));
+            if keys != Ordering::Equal {
+                return keys;
+            }
+            for ((_, x), (_, y)) in xs.iter().zip(ys.iter()) {
+                let ord = compare(x, y);
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            Ordering::Equal
+        }
+        // same rank and not a container: null, or booleans of the same value
+        _ => Ordering::Equal,
+    }
+}
+
+/// A value the query produced: borrowed from the input, or computed
+type Value<'j> = Cow<'j, JsonData<'static>>;
+
+/// Looks inside of a value with `pick`, borrowing from the input if the value itself is borrowed.
+/// Gives `null` if `pick` finds nothing
+fn project<'j>(
+    value: &Value<'j>,
+    pick: impl for<'v> Fn(&'v JsonData<'static>) -> Result<Option<&'v JsonData<'static>>>,
+) -> Result<Value<'j>> {
+    let picked = match value {
+        Cow::Borrowed(value) => pick(value)?.map(Cow::Borrowed),
+        Cow::Owned(value) => pick(value)?.cloned().map(Cow::Owned),
+    };
+    Ok(picked.unwrap_or_else(|| Cow::Owned(JsonData::null())))
+}
+
+fn field<'v>(target: &'v JsonData<'static>, name: &str) -> Result<Option<&'v JsonData<'static>>> {
+    match target.ty() {
+        // the parser already keeps only the last of any repeated keys, like jq
+        JsonDataType::Object { entries } => Ok(entries.iter()
+            .rfind(|(key, _)| key.name() == name)
+            .map(|(_, value)| value)),
+        JsonDataType::Null => Ok(None),
+        _ => bail!("Cannot index {} with \"{name}\"", target.type_name()),
+    }
+}
+
+fn index<'v>(target: &'v JsonData<'static>, idx: &JsonData<'static>) -> Result<Option<&'v JsonData<'static>>> {
+    match (target.ty(), idx.ty()) {
+        (JsonDataType::Object { .. } | JsonDataType::Null, JsonDataType::Str { .. }) => {
+            let name = idx.as_str().unwrap_or_default();
+            field(target, &name)
+        }
+        (JsonDataType::Array { elems }, JsonDataType::Number { .. }) => {
+            let n = idx.as_f64().unwrap_or(f64::NAN);
+            if n.fract() != 0.0 {
+                bail!("fractional indices are not supported natively");
+            }
+            let n = n as i64;
+            let n = if n < 0 { n + elems.len() as i64 } else { n };
+            Ok(usize::try_from(n).ok().and_then(|n| elems.get(n)))
+        }
+        (JsonDataType::Null, JsonDataType::Number { .. }) => Ok(None),
+        _ => bail!("Cannot index {} with {}", target.type_name(), idx.type_name()),
+    }
+}
+
+/// The values inside of an array or object, borrowed if the container is
+fn iterate<'j>(value: Value<'j>) -> Result<Vec<Value<'j>>> {
+    let values = match value {
+        Cow::Borrowed(value) => match value.ty() {
+            JsonDataType::Array { elems } => elems.iter().map(Cow::Borrowed).collect(),
+            JsonDataType::Object { entries } => entries.iter().map(|(_, v)| Cow::Borrowed(v)).collect(),
+            _ => bail!("Cannot iterate over {}", value.type_name()),
+        },
+        Cow::Owned(value) => match value.ty() {
+            JsonDataType::Array { elems } => elems.iter().cloned().map(Cow::Owned).collect(),
+            JsonDataType::Object { entries } => entries.iter().map(|(_, v)| Cow::Owned(v.clone())).collect(),
+            _ => bail!("Cannot iterate over {}", value.type_name()),
+        },
+    };
+    Ok(values)
+}
+
+fn eval<'j>(expr: &Expr, input: &'j JsonData<'static>, cancelled: &AtomicBool) -> Result<Vec<Value<'j>>> {
+    if cancelled.load(AtomicOrdering::Relaxed) {
+        bail!("cancelled");
+    }
+    let outputs = match expr {
+        Expr::Identity => vec![Cow::Borrowed(input)],
+        Expr::Literal(value) => vec![Cow::Owned(value.clone())],
+        Expr::Field(target, name) => eval(target, input, cancelled)?
+            .iter()
+            .map(|t| project(t, |t| field(t, name)))
+            .collect::<Result<_>>()?,
+        Expr::Index(target, idx) => {
+            let targets = eval(target, input, cancelled)?;
+            let mut out = Vec::new();
+            // the index expression sees the same input as the target, and is the outer loop
+            for i in eval(idx, input, cancelled)? {
+                for t in &targets {
+                    out.push(project(t, |t| index(t, &i))?);
+                }
+            }
+            out
+        }
+        Expr::Iterate(target) => {
+            let mut out = Vec::new();
+            for t in eval(target, input, cancelled)? {
+                out.extend(iterate(t)?);
+            }
+            out
+        }
+        Expr::Pipe(lhs, rhs) => {
+            let mut out = Vec::new();
+            for value in eval(lhs, input, cancelled)? {
+                match value {
+                    Cow::Borrowed(value) => out.extend(eval(rhs, value, cancelled)?),
+                    // whatever is picked out of a computed value has to be owned, it does not outlive this loop
+                    Cow::Owned(value) => out.extend(eval(rhs, &value, cancelled)?
+                        .into_iter()
+                        .map(|v| Cow::Owned(v.into_owned()))),
+                }
+            }
+            out
+        }
+        Expr::Comma(lhs, rhs) => {
+            let mut out = eval(lhs, input, cancelled)?;
+            out.extend(eval(rhs, input, cancelled)?);
+            out
+        }
+        Expr::Compare(op, lhs, rhs) => {
+            let lhs = eval(lhs, input, cancelled)?;
+            let mut out = Vec::new();
+            // jq runs the right hand side as the outer loop
+            for r in eval(rhs, input, cancelled)? {
+                for l in &lhs {
+                    let ord = compare(l, &r);
+                    let result = match op {
+                        CmpOp::Eq => ord == Ordering::Equal,
+                        CmpOp::Ne => ord != Ordering::Equal,
+                        CmpOp::Lt => ord == Ordering::Less,
+                        CmpOp::Le => ord != Ordering::Greater,
+                        CmpOp::Gt => ord == Ordering::Greater,
+                        CmpOp::Ge => ord != Ordering::Less,
+                    };
+                    out.push(Cow::Owned(JsonData::boolean(result)));
+                }
+            }
+            out
+        }
+        Expr::And(lhs, rhs) | Expr::Or(lhs, rhs) => {
+            let is_and = matches!(expr, Expr::And(..));
+            let mut out = Vec::new();
+            for l in eval(lhs, input, cancelled)? {
+                // short circuit: `false and _` and `true or _` do not run the right hand side
+                if l.is_truthy() != is_and {
+                    out.push(Cow::Owned(JsonData::boolean(!is_and)));
+                    continue;
+                }
+                for r in eval(rhs, input, cancelled)? {
+                    out.push(Cow::Owned(JsonData::boolean(r.is_truthy())));
+                }
+            }
+            out
+        }
+        Expr::Array(None) => vec![Cow::Owned(JsonData::array(vec![]))],
+        Expr::Array(Some(inner)) => {
+            let elems = eval(inner, input, cancelled)?
+                .into_iter()
+                .map(Cow::into_owned)
+                .collect();
+            vec![Cow::Owned(JsonData::array(elems))]
+        }
+        Expr::Object(entries) => {
+            // every combination of the outputs of the keys and values, earlier entries varying slowest.
+            // the values stay borrowed until the objects are put together at the end
+            let mut partials: Vec<Vec<(JsonKey<'static>, Value<'j>)>> = vec![vec![]];
+            for (key, value) in entries {
+                let keys = eval(key, input, cancelled)?;
+                let values = eval(value, input, cancelled)?;
+                let mut next = Vec::new();
+                for partial in &partials {
+                    for k in &keys {
+                        let Some(name) = k.as_str() else {
+                            bail!("Object keys must be strings");
+                        };
+                        for v in &values {
+                            let mut entry = partial.clone();
+                            // a repeated key overwrites the earlier one, in place
+                            match entry.iter_mut().find(|(existing, _)| existing.name() == name) {
+                                Some(existing) => existing.1 = v.clone(),
+                                None => entry.push((JsonKey::new(&name), v.clone())),
+                            }
+                            next.push(entry);
+                        }
+                    }
+                }
+                partials = next;
+            }
+            partials.into_iter()
+                .map(|entries| {
+                    let entries = entries.into_iter()
+                        .map(|(key, value)| (key, value.into_owned()))
+                        .collect();
+                    Cow::Owned(JsonData::object(entries))
+                })
+                .collect()
+        }
+        Expr::Select(cond) => {
+            let mut out = Vec::new();
+            for c in eval(cond, input, cancelled)? {
+                if c.is_truthy() {
+                    out.push(Cow::Borrowed(input));
+                }
+            }
+            out
+        }
+        Expr::Map(f) => {
+            let mapped = Expr::Array(Some(Box::new(Expr::Pipe(
+                Box::new(Expr::Iterate(Box::new(Expr::Identity))),
+                f.clone(),
+            ))));
+            eval(&mapped, input, cancelled)?
+        }
+        Expr::Keys => match input.ty() {
+            JsonDataType::Object { entries } => {
+                let keys = sorted_entries(entries)
+                    .into_iter()
+                    .map(|(k, _)| JsonData::string(&k))
+                    .collect();
+                vec![Cow::Owned(JsonData::array(keys))]
+            }
+            JsonDataType::Array { elems } => {
+                let keys = (0..elems.len())
+                    .map(|i| JsonData::number(i as f64))
+                    .collect();
+                vec![Cow::Owned(JsonData::array(keys))]
+            }
+            _ => bail!("{} has no keys", input.type_name()),
+        },
+        Expr::Length => {
+            let len = match input.ty() {
+                JsonDataType::Object { entries } => entries.len() as f64,
+                JsonDataType::Array { elems } => elems.len() as f64,
+                JsonDataType::Str { .. } => input.as_str().unwrap_or_default().chars().count() as f64,
+                JsonDataType::Number { .. } => input.as_f64().unwrap_or(0.0).abs(),
+                JsonDataType::Null => 0.0,
+                JsonDataType::Boolean { .. } => bail!("boolean has no length"),
+            };
+            vec![Cow::Owned(JsonData::number(len))]
+        }
+        Expr::Not => vec![Cow::Owned(JsonData::boolean(!input.is_truthy()))],
+        Expr::Empty => vec![],
+    };
+    Ok(outputs)
+}
+
+/// Runs `query` over `input` natively, giving back the values rather than their text.
+/// Values that come straight from the input are borrowed from it
+pub fn values<'j>(query: &str, input: &'j JsonData<'static>) -> Result<Vec<Cow<'j, JsonData<'static>>>> {
+    eval(&parse(query)?, input, &AtomicBool::new(false))
+}
+
+/// A query that is known to be within the supported subset, ready to run
+#[derive(Debug, Clone)]
+pub struct Program {
+    expr: Expr,
+    options: JqOptions,
+}
+
+/// Checks that the query and the options only use what the native evaluator supports, without running anything.
+/// Anything that fails here should go to the real jq instead
+pub fn compile(query: &str, options: &JqOptions) -> Result<Program> {
+    // there are no variables here, and jq checks these even when the query does not use them
+    if !options.args.is_empty() || !options.json_args.is_empty() || !options.slurp_files.is_empty() {
+        bail!("--arg, --argjson and --slurpfile are not supported");
+    }
+    let expr = parse(query)?;
+    Ok(Program { expr, options: options.clone() })
+}
+
+impl Program {
+    /// Runs over `inputs`, every value of the input in one array as -s reads it, producing the same text jq would print.
+    /// Without -s the query runs over each value in turn, and with -n once over `null`.
+    /// Gives up with an error as soon as it sees `cancelled` set
+    pub fn run(&self, inputs: &JsonData<'static>, cancelled: &AtomicBool) -> Result<String> {
+        let null = JsonData::null();
+        let inputs: Vec<&JsonData<'static>> = match (self.options.null_input, self.options.slurp, inputs.ty()) {
+            (true, _, _) => vec![&null],
+            (false, true, _) => vec![inputs],
+            (false, false, JsonDataType::Array { elems }) => elems.iter().collect(),
+            (false, false, _) => vec![inputs],
+        };
+
+        let dump_options = DumpOptions {
+            compact: self.options.compact_output,
+            sort_keys: self.options.sort_keys,
+        };
+
+        let mut out = String::new();
+        for input in inputs {
+            for value in eval(&self.expr, input, cancelled)? {
+                if cancelled.load(AtomicOrdering::Relaxed) {
+                    bail!("cancelled");
+                }
+                match value.as_str() {
+                    Some(s) if self.options.raw_output => out.push_str(&s),
+                    _ => out.push_str(&json::dumps(&value, dump_options)),
+                }
+                out.push('\n');
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Runs `query` natively over `inputs`, every value of the input in one array, producing the same text jq would print.
+/// Fails if the query (or the options) use anything outside of the supported subset.
+pub fn run(query: &str, inputs: &JsonData<'static>, options: &JqOptions) -> Result<String> {
+    compile(query, options)?.run(inputs, &AtomicBool::new(false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inputs(source: &'static str) -> JsonData<'static> {
+        JsonData::array(json::loads_all(source).expect("test input should parse"))
+    }
+
+    fn run_compact(query: &str, source: &'static str) -> String {
+        let input = inputs(source);
+        let options = JqOptions {
+            compact_output: true,
+            ..Default::default()
+        };
+        run(query, &input, &options).expect("query should run natively")
+    }
+
+    #[test]
+    fn identity_and_fields() {
+        let source = r#"{"a": {"b": [1, 2, 3]}, "c d": true}"#;
+        assert_eq!(run_compact("", source), "{\"a\":{\"b\":[1,2,3]},\"c d\":true}\n");
+        assert_eq!(run_compact(".a.b", source), "[1,2,3]\n");
+        assert_eq!(run_compact(".\"c d\"", source), "true\n");
+        assert_eq!(run_compact(".[\"c d\"]", source), "true\n");
+        assert_eq!(run_compact(".missing", source), "null\n");
+    }
+
+    #[test]
+    fn indexing_and_iterating() {
+        let source = "[[1, 2], [3, 4]]";
+        assert_eq!(run_compact(".[1]", source), "[3,4]\n");
+        assert_eq!(run_compact(".[-1][0]", source), "3\n");
+        assert_eq!(run_compact(".[5]", source), "null\n");
+        assert_eq!(run_compact(".[][]", source), "1\n2\n3\n4\n");
+        assert_eq!(run_compact("[.[][0,1]]", source), "[1,3,2,4]\n");
+    }
+
+    #[test]
+    fn pipes_and_commas() {
+        let source = r#"{"a": 1, "b": 2}"#;
+        assert_eq!(run_compact(".a, .b", source), "1\n2\n");
+        assert_eq!(run_compact("[.[]] | length", source), "2\n");
+    }
+
+    #[test]
+    fn select_map_keys() {
+        let source = r#"[{"id": 1, "ok": true}, {"id": 2, "ok": false}, {"id": 3, "ok": true}]"#;
+        assert_eq!(run_compact("map(select(.ok) | .id)", source), "[1,3]\n");
+        assert_eq!(run_compact(".[] | select(.id >= 2 and .ok) | .id", source), "3\n");
+        assert_eq!(run_compact(".[0] | keys", source), "[\"id\",\"ok\"]\n");
+        assert_eq!(run_compact("keys", source), "[0,1,2]\n");
+    }
+
+    #[test]
+    fn comparisons_follow_jq_order() {
+        assert_eq!(run_compact("[(1,2) == (1,3)]", "null"), "[true,false,false,false]\n");
+        assert_eq!(run_compact("[null < false, false < 0, 0 < \"\", \"\" < [], [] < {}]", "null"), "[true,true,true,true,true]\n");
+        assert_eq!(run_compact("1 == 1.0", "null"), "true\n");
+        assert_eq!(run_compact("{\"a\": 1, \"b\": 2} == {\"b\": 2, \"a\": 1}", "null"), "true\n");
+    }
+
+    #[test]
+    fn object_construction() {
+        let source = r#"{"a": 1, "b": [3, 4]}"#;
+        assert_eq!(run_compact("{a, \"x\": .b[0]}", source), "{\"a\":1,\"x\":3}\n");
+        assert_eq!(run_compact("[{a: (1,2), b: .b[]}]", source), "[{\"a\":1,\"b\":3},{\"a\":1,\"b\":4},{\"a\":2,\"b\":3},{\"a\":2,\"b\":4}]\n");
+        assert_eq!(run_compact("{(\"k\"): .a}", source), "{\"k\":1}\n");
+    }
+
+    #[test]
+    fn output_matches_jq_for_odd_input() {
+        assert_eq!(run_compact(".a", r#"{"a": 1, "a": 2}"#), "2\n");
+        assert_eq!(run_compact(".", r#"{"a": 1, "b": 0, "a": 2}"#), "{\"a\":2,\"b\":0}\n");
+        assert_eq!(run_compact(".", "[1.0, 1e2, 1e300]"), "[1,100,1e+300]\n");
+    }
+
+    #[test]
+    fn cancelled_runs_stop() {
+        let input = inputs("[1, 2, 3]");
+        let program = compile(".[]", &JqOptions::default()).expect("should compile");
+        assert!(program.run(&input, &AtomicBool::new(true)).is_err());
+        assert_eq!(program.run(&input, &AtomicBool::new(false)).expect("should run"), "1\n2\n3\n");
+    }
+
+    #[test]
+    fn raw_output() {
+        let input = inputs(r#"["a\"b", 1]"#);
+        let options = JqOptions {
+            raw_output: true,
+            ..Default::default()
+        };
+        assert_eq!(run(".[]", &input, &options).expect("should run"), "a\"b\n1\n");
+    }
+
+    #[test]
+    fn unsupported_queries_fail() {
+        let input = inputs("{}");
+        let options = JqOptions::default();
+        for query in [".a |= 1", "$x", "..", "\"\\(.a)\"", "to_entries", ".[1:2]", ".a + 1"] {
+            assert!(run(query, &input, &options).is_err(), "{query} should not be supported");
+        }
+    }
+
+    #[test]
+    fn unsupported_options_fail() {
+        let input = inputs("{}");
+        let options = JqOptions {
+            args: vec![("x".to_string(), "1".to_string())],
+            ..JqOptions::default()
+        };
+        assert!(run(".", &input, &options).is_err());
+        let options = JqOptions {
+            slurp_files: vec![("x".to_string(), "missing.json".to_string())],
+            ..JqOptions::default()
+        };
+        assert!(compile(".", &options).is_err());
+    }
+
+    #[test]
+    fn runtime_errors_fail() {
+        let input = inputs("[1]");
+        assert!(run(".a", &input, &JqOptions::default()).is_err());
+        assert!(run(".[0][]", &input, &JqOptions::default()).is_err());
+    }
+}
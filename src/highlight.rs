@@ -0,0 +1,248 @@
+//! Picks out the parts of a jq query, so the editor can color them.
+
+/// What a char of the query is part of
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum QueryTokenKind {
+    Plain,
+    /// `.foo`, `.`, `..`
+    Path,
+    /// `$foo`
+    Variable,
+    /// A function name like `map` or `select`
+    Function,
+    /// `if`, `then`, `reduce`, `def`, ...
+    Keyword,
+    /// `@base64`, `@csv`, ...
+    Format,
+    String,
+    /// The `\(` and `)` around code inside of a string
+    Interpolation,
+    Number,
+    /// `|`, `,`, `+`, `==`, `//`, `|=`, ...
+    Operator,
+    /// `(`, `)`, `[`, `]`, `{`, `}`
+    Bracket,
+    /// From `#` to the end of the line
+    Comment,
+}
+
+const KEYWORDS: &[&str] = &[
+    "if", "then", "elif", "else", "end", "as", "def", "reduce", "foreach", "try", "catch",
+    "label", "import", "include", "and", "or", "__loc__",
+];
+
+const OPERATOR_CHARS: &str = "|,+-*/%=<>!?;:";
+
+/// The kind of every char of the query, and the brackets that have no partner
+#[derive(Debug)]
+pub struct Highlighted {
+    /// One for each char of the query, newlines included
+    pub kinds: Vec<QueryTokenKind>,
+    /// Char indices of brackets that are never closed, or close something they do not match
+    pub unbalanced: Vec<usize>,
+}
+
+fn is_ident_start(ch: char) -> bool {
+    ch.is_ascii_alphabetic() || ch == '_'
+}
+
+fn is_ident_char(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || ch == '_'
+}
+
+/// An open bracket, or the `\(` of a string interpolation
+struct Open {
+    ch: char,
+    at: usize,
+    interpolation: bool,
+}
+
+pub fn highlight(query: &str) -> Highlighted {
+    let chars: Vec<char> = query.chars().collect();
+    let mut kinds = vec![QueryTokenKind::Plain; chars.len()];
+    let mut unbalanced = Vec::new();
+    let mut opens: Vec<Open> = Vec::new();
+    let mut in_string = false;
+
+    // the end of the identifier (or name) starting at `from`
+    let ident_end = |from: usize| -> usize {
+        (from..chars.len()).find(|&i| !is_ident_char(chars[i])).unwrap_or(chars.len())
+    };
+
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if in_string {
+            match ch {
+                '\\' if next == Some('(') => {
+                    kinds[i] = QueryTokenKind::Interpolation;
+                    kinds[i + 1] = QueryTokenKind::Interpolation;
+                    opens.push(Open { ch: '(', at: i + 1, interpolation: true });
+                    in_string = false;
+                    i += 2;
+                }
+                '\\' => {
+                    kinds[i] = QueryTokenKind::String;
+                    if i + 1 < chars.len() {
+                        kinds[i + 1] = QueryTokenKind::String;
+                    }
+                    i += 2;
+                }
+                '"' => {
+                    kinds[i] = QueryTokenKind::String;
+                    in_string = false;
+                    i += 1;
+                }
+                _ => {
+                    kinds[i] = QueryTokenKind::String;
+                    i += 1;
+                }
+            }
+            continue;
+        }
+
+        let (kind, end) = match ch {
+            '"' => {
+                in_string = true;
+                (QueryTokenKind::String, i + 1)
+            }
+            '#' => {
+                let end = (i..chars.len()).find(|&j| chars[j] == '\n').unwrap_or(chars.len());
+                (QueryTokenKind::Comment, end)
+            }
+            '.' if next == Some('.') => (QueryTokenKind::Path, i + 2),
+            '.' if next.is_some_and(is_ident_start) => (QueryTokenKind::Path, ident_end(i + 1)),
+            '.' => (QueryTokenKind::Path, i + 1),
+            '$' if next.is_some_and(is_ident_start) => (QueryTokenKind::Variable, ident_end(i + 1)),
+            '@' if next.is_some_and(is_ident_start) => (QueryTokenKind::Format, ident_end(i + 1)),
+            ch if is_ident_start(ch) => {
+                let end = ident_end(i);
+                let word: String = chars[i..end].iter().collect();
+                let kind = if KEYWORDS.contains(&word.as_str()) {
+                    QueryTokenKind::Keyword
+                } else {
+                    QueryTokenKind::Function
+                };
+                (kind, end)
+            }
+            ch if ch.is_ascii_digit() => {
+                let end = (i..chars.len())
+                    .find(|&j| !(chars[j].is_ascii_alphanumeric() || chars[j] == '.'))
+                    .unwrap_or(chars.len());
+                (QueryTokenKind::Number, end)
+            }
+            '(' | '[' | '{' => {
+                opens.push(Open { ch, at: i, interpolation: false });
+                (QueryTokenKind::Bracket, i + 1)
+            }
+            ')' | ']' | '}' => {
+                let partner = match ch {
+                    ')' => '(',
+                    ']' => '[',
+                    _ => '{',
+                };
+                match opens.last() {
+                    Some(open) if open.ch == partner => {
+                        let open = opens.pop().expect("just looked at it");
+                        if open.interpolation {
+                            // back into the string the interpolation is in
+                            in_string = true;
+                            (QueryTokenKind::Interpolation, i + 1)
+                        } else {
+                            (QueryTokenKind::Bracket, i + 1)
+                        }
+                    }
+                    _ => {
+                        unbalanced.push(i);
+                        (QueryTokenKind::Bracket, i + 1)
+                    }
+                }
+            }
+            ch if OPERATOR_CHARS.contains(ch) => (QueryTokenKind::Operator, i + 1),
+            _ => (QueryTokenKind::Plain, i + 1),
+        };
+        for k in &mut kinds[i..end] {
+            *k = kind;
+        }
+        i = end;
+    }
+
+    unbalanced.extend(opens.iter().map(|open| open.at));
+    unbalanced.sort_unstable();
+    Highlighted { kinds, unbalanced }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use QueryTokenKind as Kind;
+
+    /// The runs of each kind, as (text, kind)
+    fn runs(query: &str) -> Vec<(String, QueryTokenKind)> {
+        let highlighted = highlight(query);
+        let mut runs: Vec<(String, QueryTokenKind)> = Vec::new();
+        for (ch, kind) in query.chars().zip(highlighted.kinds) {
+            match runs.last_mut() {
+                Some((text, last)) if *last == kind => text.push(ch),
+                _ => runs.push((ch.to_string(), kind)),
+            }
+        }
+        runs.retain(|(text, _)| !text.trim().is_empty());
+        runs
+    }
+
+    fn run(text: &str, kind: QueryTokenKind) -> (String, QueryTokenKind) {
+        (text.to_string(), kind)
+    }
+
+    #[test]
+    fn paths_functions_and_operators() {
+        assert_eq!(runs(".a.b | map(.c) // $x"), vec![
+            run(".a.b", Kind::Path),
+            run("|", Kind::Operator),
+            run("map", Kind::Function),
+            run("(", Kind::Bracket),
+            run(".c", Kind::Path),
+            run(")", Kind::Bracket),
+            run("//", Kind::Operator),
+            run("$x", Kind::Variable),
+        ]);
+    }
+
+    #[test]
+    fn keywords_and_comments() {
+        assert_eq!(runs("if . then 1 else @csv end # done"), vec![
+            run("if", Kind::Keyword),
+            run(".", Kind::Path),
+            run("then", Kind::Keyword),
+            run("1", Kind::Number),
+            run("else", Kind::Keyword),
+            run("@csv", Kind::Format),
+            run("end", Kind::Keyword),
+            run("# done", Kind::Comment),
+        ]);
+    }
+
+    #[test]
+    fn string_interpolation() {
+        assert_eq!(runs(r#""a \(.b + "c") \"d""#), vec![
+            run("\"a ", Kind::String),
+            run("\\(", Kind::Interpolation),
+            run(".b", Kind::Path),
+            run("+", Kind::Operator),
+            run("\"c\"", Kind::String),
+            run(")", Kind::Interpolation),
+            run(" \\\"d\"", Kind::String),
+        ]);
+    }
+
+    #[test]
+    fn unbalanced_brackets() {
+        assert_eq!(highlight("map(.a]").unbalanced, vec![3, 6]);
+        assert_eq!(highlight("[.a, (.b)").unbalanced, vec![0]);
+        assert_eq!(highlight(r#""(" | ")""#).unbalanced, Vec::<usize>::new());
+        assert_eq!(highlight(r#""\(.a""#).unbalanced, vec![2]);
+    }
+}
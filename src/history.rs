@@ -0,0 +1,403 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf}
+};
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+const HISTORY_FILE_NAME: &str = "history.tsv";
+
+/// How many queries we remember. Past this, the oldest are forgotten
+const MAX_HISTORY_ENTRIES: usize = 1000;
+
+/// What we write for the input when it came from stdin
+const STDIN_NAME: &str = "-";
+
+/// One query the user has run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub timestamp: DateTime<Utc>,
+    /// The file the query was run against, `-` for stdin
+    pub input: String,
+    pub query: String,
+}
+
+/// Every query that was submitted successfully, oldest first.
+/// Stored as one tab separated `timestamp, input, query` line per entry.
+#[derive(Debug)]
+pub struct History {
+    path: PathBuf,
+    pub entries: Vec<HistoryEntry>,
+    /// While walking with alt-,/alt-., the entry being shown
+    walk: Option<usize>,
+    /// What was in the editor before we started walking
+    draft: String,
+}
+
+/// Escapes tabs, newlines, carriage returns and backslashes so an entry stays on one line
+pub fn escape_field(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            ch => out.push(ch),
+        }
+    }
+    out
+}
+
+/// The inverse of `escape_field`
+pub fn unescape_field(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+fn parse_line(line: &str) -> Option<HistoryEntry> {
+    let mut fields = line.splitn(3, '\t');
+    let timestamp = fields.next()?.parse().ok()?;
+    let input = unescape_field(fields.next()?);
+    let query = unescape_field(fields.next()?);
+    Some(HistoryEntry { timestamp, input, query })
+}
+
+fn format_line(entry: &HistoryEntry) -> String {
+    format!(
+        "{}\t{}\t{}\n",
+        entry.timestamp.to_rfc3339(),
+        escape_field(&entry.input),
+        escape_field(&entry.query)
+    )
+}
+
+/// The name to record for an input file
+pub fn input_name(input_filename: Option<&Path>) -> String {
+    match input_filename {
+        Some(path) => path.canonicalize()
+            .unwrap_or_else(|_| path.to_path_buf())
+            .display()
+            .to_string(),
+        None => STDIN_NAME.to_string(),
+    }
+}
+
+impl History {
+    /// Reads the history in `data_dir`. A missing file is just an empty history
+    pub fn load(data_dir: &Path) -> Result<History> {
+        let path = data_dir.join(HISTORY_FILE_NAME);
+        let mut entries: Vec<HistoryEntry> = if path.exists() {
+            fs::read_to_string(&path)
+                .with_context(|| format!("reading history from {}", path.display()))?
+                .lines()
+                .filter_map(|line| {
+                    let entry = parse_line(line);
+                    if entry.is_none() {
+                        log::warn!("skipping malformed history line: {line:?}");
+                    }
+                    entry
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        log::info!("loaded {} history entries from {}", entries.len(), path.display());
+        // the file itself is cut down the next time a query is added
+        let excess = entries.len().saturating_sub(MAX_HISTORY_ENTRIES);
+        entries.drain(..excess);
+        Ok(History::from_entries(path, entries))
+    }
+
+    /// A history with nothing in it yet, which will be saved in `data_dir`
+    pub fn empty(data_dir: &Path) -> History {
+        History::from_entries(data_dir.join(HISTORY_FILE_NAME), Vec::new())
+    }
+
+    pub fn from_entries(path: PathBuf, entries: Vec<HistoryEntry>) -> History {
+        History {
+            path,
+            entries,
+            walk: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Records a query, both in memory and on disk. Repeating the last query is not recorded again
+    pub fn add(&mut self, input: &str, query: &str) -> Result<()> {
+        self.walk = None;
+        if query.trim().is_empty() {
+            return Ok(());
+        }
+        if self.entries.last().is_some_and(|last| last.query == query && last.input == input) {
+            return Ok(());
+        }
+
+        let entry = HistoryEntry {
+            timestamp: Utc::now(),
+            input: input.to_string(),
+            query: query.to_string(),
+        };
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("creating history folder at {}", dir.display()))?;
+        }
+
+        // the file only ever needs appending to, until it is full
+        let full = self.entries.len() >= MAX_HISTORY_ENTRIES;
+        let line = format_line(&entry);
+        self.entries.push(entry);
+        if full {
+            let excess = self.entries.len() - MAX_HISTORY_ENTRIES;
+            self.entries.drain(..excess);
+            return self.rewrite();
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("opening history file {}", self.path.display()))?;
+        file.write_all(line.as_bytes())
+            .with_context(|| format!("writing to history file {}", self.path.display()))?;
+        Ok(())
+    }
+
+    /// Writes out the whole history, replacing what is on disk
+    fn rewrite(&self) -> Result<()> {
+        let contents: String = self.entries.iter().map(format_line).collect();
+        fs::write(&self.path, contents)
+            .with_context(|| format!("writing history file {}", self.path.display()))
+    }
+
+    /// The query before the one being shown (alt-,). `current` is what is in the editor now
+    pub fn older(&mut self, current: &str) -> Option<&str> {
+        let next = match self.walk {
+            None => {
+                self.draft = current.to_string();
+                self.entries.len().checked_sub(1)?
+            }
+            Some(idx) => idx.checked_sub(1)?,
+        };
+        self.walk = Some(next);
+        Some(self.entries[next].query.as_str())
+    }
+
+    /// The query after the one being shown (alt-.). Walking past the newest gives back the draft
+    pub fn newer(&mut self) -> Option<&str> {
+        let idx = self.walk?;
+        if idx + 1 < self.entries.len() {
+            self.walk = Some(idx + 1);
+            Some(self.entries[idx + 1].query.as_str())
+        } else {
+            self.walk = None;
+            Some(self.draft.as_str())
+        }
+    }
+
+    /// Forget where we were walking, e.g. because the user edited the query
+    pub fn stop_walking(&mut self) {
+        self.walk = None;
+    }
+}
+
+/// Scores how well `needle` matches `haystack` as a subsequence, ignoring case.
+/// `None` if it does not match at all. Higher is better: runs of consecutive
+/// characters and matches at the start of words count for more.
+pub fn fuzzy_score(needle: &str, haystack: &str) -> Option<i64> {
+    if needle.is_empty() {
+        // everything matches equally, so the newest stays on top
+        return Some(0);
+    }
+    let haystack: Vec<char> = haystack.chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut prev_match: Option<usize> = None;
+
+    for n in needle.chars() {
+        let n = n.to_ascii_lowercase();
+        let found = (pos..haystack.len()).find(|&i| haystack[i].to_ascii_lowercase() == n)?;
+
+        score += 1;
+        if prev_match.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        }
+        let at_word_start = found == 0 || !haystack[found - 1].is_alphanumeric();
+        if at_word_start {
+            score += 3;
+        }
+        prev_match = Some(found);
+        pos = found + 1;
+    }
+
+    // prefer shorter queries when the match is otherwise the same
+    Some(score * 1000 - haystack.len() as i64)
+}
+
+/// The state of the alt-/ popup
+#[derive(Debug)]
+pub struct HistorySearch {
+    pub needle: String,
+    /// The distinct queries from the history, newest first
+    candidates: Vec<String>,
+    /// The candidates that match the needle, best first
+    pub matches: Vec<String>,
+    pub selected: usize,
+}
+
+impl HistorySearch {
+    pub fn new(history: &History) -> HistorySearch {
+        let mut candidates: Vec<String> = Vec::new();
+        for entry in history.entries.iter().rev() {
+            if !candidates.contains(&entry.query) {
+                candidates.push(entry.query.clone());
+            }
+        }
+        let mut search = HistorySearch {
+            needle: String::new(),
+            candidates,
+            matches: Vec::new(),
+            selected: 0,
+        };
+        search.refilter();
+        search
+    }
+
+    fn refilter(&mut self) {
+        let mut scored: Vec<(i64, usize)> = self.candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, query)| fuzzy_score(&self.needle, query).map(|score| (score, i)))
+            .collect();
+        // best score first, newest first among equals
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        self.matches = scored.into_iter()
+            .map(|(_, i)| self.candidates[i].clone())
+            .collect();
+        self.selected = 0;
+    }
+
+    pub fn push_char(&mut self, ch: char) {
+        self.needle.push(ch);
+        self.refilter();
+    }
+
+    pub fn pop_char(&mut self) {
+        self.needle.pop();
+        self.refilter();
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_query(&self) -> Option<&str> {
+        self.matches.get(self.selected).map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(queries: &[&str]) -> History {
+        let entries = queries.iter()
+            .map(|q| HistoryEntry {
+                timestamp: Utc::now(),
+                input: "-".to_string(),
+                query: q.to_string(),
+            })
+            .collect();
+        History::from_entries(PathBuf::from("/nonexistent/history.tsv"), entries)
+    }
+
+    #[test]
+    fn escape_round_trips() {
+        let s = "a\tb\nc\\d\\n";
+        assert_eq!(escape_field(s), "a\\tb\\nc\\\\d\\\\n");
+        assert_eq!(unescape_field(&escape_field(s)), s);
+        assert_eq!(escape_field(".a\r\n"), ".a\\r\\n");
+        assert_eq!(unescape_field(&escape_field("\r")), "\r");
+    }
+
+    #[test]
+    fn add_forgets_the_oldest_when_full() {
+        let dir = tempfile::tempdir().expect("create a temp dir");
+        let mut history = History::empty(dir.path());
+        for i in 0..MAX_HISTORY_ENTRIES + 5 {
+            history.add("-", &format!(".[{i}]")).expect("add to the history");
+        }
+        assert_eq!(history.entries.len(), MAX_HISTORY_ENTRIES);
+        assert_eq!(history.entries[0].query, ".[5]");
+
+        let loaded = History::load(dir.path()).expect("load the history");
+        assert_eq!(loaded.entries, history.entries);
+    }
+
+    #[test]
+    fn line_round_trips() {
+        let entry = HistoryEntry {
+            timestamp: "2024-07-01T12:00:00Z".parse().expect("valid timestamp"),
+            input: "/tmp/in put.json".to_string(),
+            query: ".a |\n\t.b".to_string(),
+        };
+        let line = format_line(&entry);
+        assert_eq!(parse_line(line.trim_end_matches('\n')), Some(entry));
+    }
+
+    #[test]
+    fn walk_history() {
+        let mut history = history(&[".a", ".b"]);
+        assert_eq!(history.older("draft"), Some(".b"));
+        assert_eq!(history.older(".b"), Some(".a"));
+        assert_eq!(history.older(".a"), None);
+        assert_eq!(history.newer(), Some(".b"));
+        assert_eq!(history.newer(), Some("draft"));
+        assert_eq!(history.newer(), None);
+    }
+
+    #[test]
+    fn fuzzy_matches_subsequences() {
+        assert!(fuzzy_score("abc", "a.b.c").is_some());
+        assert!(fuzzy_score("abc", "acb").is_none());
+        assert!(fuzzy_score("", "anything").is_some());
+        // consecutive beats scattered
+        assert!(fuzzy_score("keys", ".keys") > fuzzy_score("keys", ".k.e.y.s"));
+    }
+
+    #[test]
+    fn search_dedupes_and_ranks() {
+        let history = history(&[".items[] | .id", "keys", ".items[] | .id", ".ids"]);
+        let mut search = HistorySearch::new(&history);
+        assert_eq!(search.matches, vec![".ids", ".items[] | .id", "keys"]);
+
+        search.push_char('i');
+        search.push_char('d');
+        assert_eq!(search.selected_query(), Some(".ids"));
+        search.select_next();
+        assert_eq!(search.selected_query(), Some(".items[] | .id"));
+    }
+}
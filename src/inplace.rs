@@ -0,0 +1,78 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf}
+};
+
+use anyhow::{Context, Result};
+use tempfile::NamedTempFile;
+
+/// Where the backup of `path` goes: `file.json` is backed up to `file.json.bak`
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+/// Replaces the contents of `path` without ever leaving it half written: the new contents go to
+/// a temporary file next to it, which is then renamed over it. Returns where the backup went, if asked for one
+pub fn replace_file(path: &Path, contents: &str, backup: bool) -> Result<Option<PathBuf>> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+
+    let mut temp = NamedTempFile::new_in(dir)
+        .with_context(|| format!("creating a temporary file in {}", dir.display()))?;
+    temp.write_all(contents.as_bytes())
+        .with_context(|| format!("writing to temporary file {}", temp.path().display()))?;
+    temp.as_file().sync_all()
+        .with_context(|| format!("syncing temporary file {}", temp.path().display()))?;
+
+    // keep the permissions the file had
+    if let Ok(metadata) = fs::metadata(path) {
+        fs::set_permissions(temp.path(), metadata.permissions())
+            .with_context(|| format!("copying the permissions of {}", path.display()))?;
+    }
+
+    let backup_path = if backup {
+        let backup_path = backup_path(path);
+        fs::copy(path, &backup_path)
+            .with_context(|| format!("backing up {} to {}", path.display(), backup_path.display()))?;
+        log::info!("backed up {} to {}", path.display(), backup_path.display());
+        Some(backup_path)
+    } else {
+        None
+    };
+
+    temp.persist(path)
+        .with_context(|| format!("replacing {}", path.display()))?;
+    log::info!("replaced {}", path.display());
+    Ok(backup_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_names() {
+        assert_eq!(backup_path(Path::new("a/file.json")), PathBuf::from("a/file.json.bak"));
+        assert_eq!(backup_path(Path::new("file")), PathBuf::from("file.bak"));
+    }
+
+    #[test]
+    fn replaces_and_backs_up() {
+        let dir = tempfile::tempdir().expect("create temp dir");
+        let path = dir.path().join("data.json");
+        fs::write(&path, "old\n").expect("write test file");
+
+        let backup = replace_file(&path, "new\n", true).expect("replace file");
+        assert_eq!(fs::read_to_string(&path).expect("read file"), "new\n");
+        let backup = backup.expect("asked for a backup");
+        assert_eq!(fs::read_to_string(backup).expect("read backup"), "old\n");
+
+        assert_eq!(replace_file(&path, "newer\n", false).expect("replace file"), None);
+        assert_eq!(fs::read_to_string(&path).expect("read file"), "newer\n");
+    }
+}
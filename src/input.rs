@@ -5,11 +5,13 @@ use ratatui::
             self, 
             Event,
             KeyCode,
-            KeyEvent, KeyEventKind,
+            KeyEvent, KeyEventKind, KeyModifiers,
         }
 ;
 
-use crate::app::App;
+use tui_textarea::CursorMove;
+
+use crate::app::{App, Focus, Popup};
 
 const POLL_DURATION: std::time::Duration = std::time::Duration::from_millis(50);
 
@@ -21,6 +23,12 @@ pub fn handle_events(app: &mut App) -> Result<()> {
     // Process the event. The query editor should be shown every input, except for Esc and Enter
     // because we are hiding those from the text area
     let ev = event::read()?;
+    if app.popup.is_some() && handle_popup_event(app, &ev) {
+        return Ok(());
+    }
+    if app.focus == Focus::Result && handle_result_event(app, &ev) {
+        return Ok(());
+    }
     match ev {
         // Quite the app on `Esc`
         Event::Key(KeyEvent { kind, code: KeyCode::Esc, .. }) => {
@@ -28,12 +36,117 @@ pub fn handle_events(app: &mut App) -> Result<()> {
                 app.is_running = false;
             }
         }
+        // Start a new line in the query on "alt-enter" or "shift-enter"
+        Event::Key(KeyEvent { kind, code: KeyCode::Enter, modifiers, .. })
+            if modifiers.intersects(KeyModifiers::ALT | KeyModifiers::SHIFT) => {
+            if kind == KeyEventKind::Press {
+                app.insert_newline();
+            }
+        }
+        // The keys below leave the query editor's own keys alone: it uses ctrl-x, ctrl-c, ctrl-w, ctrl-p, ctrl-n
+        // and ctrl-r to cut, copy, delete a word, move between lines and redo
+        //
+        // Quit and print the query as a shell command on "alt-x"
+        Event::Key(KeyEvent { kind, code: KeyCode::Char('x'), modifiers, .. }) if modifiers.contains(KeyModifiers::ALT) => {
+            if kind == KeyEventKind::Press {
+                app.quit_with_command();
+            }
+        }
+        // Quit without printing anything, exiting with an error, on "ctrl-q"
+        Event::Key(KeyEvent { kind, code: KeyCode::Char('q'), modifiers, .. }) if modifiers.contains(KeyModifiers::CONTROL) => {
+            if kind == KeyEventKind::Press {
+                app.abort();
+            }
+        }
         // Submit a new query on "enter"
         Event::Key(KeyEvent { kind, code: KeyCode::Enter, .. }) => {
             if kind == KeyEventKind::Press {
-                app.submit_query();
+                app.confirm_query();
             }
         },
+        // Kill the running query on "alt-k"
+        Event::Key(KeyEvent { kind, code: KeyCode::Char('k'), modifiers, .. }) if modifiers.contains(KeyModifiers::ALT) => {
+            if kind == KeyEventKind::Press {
+                app.cancel_query();
+            }
+        }
+        // Flip the jq flags on "alt-<flag>", the same letters jq uses
+        Event::Key(KeyEvent { kind, code: KeyCode::Char(ch), modifiers, .. })
+            if modifiers.contains(KeyModifiers::ALT) && matches!(ch, 'r' | 's' | 'c' | 'S') => {
+            if kind == KeyEventKind::Press {
+                match ch {
+                    'r' => app.toggle_option(|opts| &mut opts.raw_output),
+                    's' => app.toggle_option(|opts| &mut opts.slurp),
+                    'c' => app.toggle_option(|opts| &mut opts.compact_output),
+                    _ => app.toggle_option(|opts| &mut opts.sort_keys),
+                }
+            }
+        }
+        // Walk the history with "alt-," (older) and "alt-." (newer), search it with "alt-/"
+        Event::Key(KeyEvent { kind, code: KeyCode::Char(ch @ (',' | '.' | '/')), modifiers, .. })
+            if modifiers.contains(KeyModifiers::ALT) => {
+            if kind == KeyEventKind::Press {
+                match ch {
+                    ',' => app.history_older(),
+                    '.' => app.history_newer(),
+                    _ => app.open_history_search(),
+                }
+            }
+        }
+        // Save the query under a name with "ctrl-s", open a saved one with "ctrl-o"
+        Event::Key(KeyEvent { kind, code: KeyCode::Char(ch @ ('s' | 'o')), modifiers, .. })
+            if modifiers.contains(KeyModifiers::CONTROL) => {
+            if kind == KeyEventKind::Press {
+                match ch {
+                    's' => app.open_save_form(),
+                    _ => app.open_saved_picker(),
+                }
+            }
+        }
+        // Write the query back to its --from-file with "alt-w"
+        Event::Key(KeyEvent { kind, code: KeyCode::Char('w'), modifiers, .. }) if modifiers.contains(KeyModifiers::ALT) => {
+            if kind == KeyEventKind::Press {
+                app.write_query_file();
+            }
+        }
+        // Write the output over the file being edited in place on F2, after confirming
+        Event::Key(KeyEvent { kind, code: KeyCode::F(2), .. }) => {
+            if kind == KeyEventKind::Press {
+                app.request_write();
+            }
+        }
+        // Switch between the output and the diff against the input on F3
+        Event::Key(KeyEvent { kind, code: KeyCode::F(3), .. }) => {
+            if kind == KeyEventKind::Press {
+                app.toggle_diff_view();
+            }
+        }
+        // Move between the query editor and the result viewer on F4
+        Event::Key(KeyEvent { kind, code: KeyCode::F(4), .. }) => {
+            if kind == KeyEventKind::Press {
+                app.toggle_focus();
+            }
+        }
+        // Step through the stages of the pipeline with F5 (on/off), F6 (previous) and F7 (next)
+        Event::Key(KeyEvent { kind, code: KeyCode::F(n @ 5..=7), .. }) => {
+            if kind == KeyEventKind::Press {
+                match n {
+                    5 => app.toggle_inspector(),
+                    6 => app.step_inspector(-1),
+                    _ => app.step_inspector(1),
+                }
+            }
+        }
+        // Move between the lines of the query on "alt-up" and "alt-down"
+        Event::Key(KeyEvent { kind, code: code @ (KeyCode::Up | KeyCode::Down), modifiers, .. })
+            if modifiers.contains(KeyModifiers::ALT) => {
+            if kind == KeyEventKind::Press {
+                match code {
+                    KeyCode::Up => app.move_query_cursor(CursorMove::Up),
+                    _ => app.move_query_cursor(CursorMove::Down),
+                }
+            }
+        }
         Event::Key(KeyEvent { code: KeyCode::Up, .. }) => {
             // Scrolling the text area up
             app.scroll_up();
@@ -42,13 +155,136 @@ pub fn handle_events(app: &mut App) -> Result<()> {
             // Scrolling the text area up
             app.scroll_down();
         }
-        Event::Key(KeyEvent { code: KeyCode::Tab | KeyCode::BackTab, .. }) => {
+        // Complete the path at the cursor on "tab"
+        Event::Key(KeyEvent { kind, code: KeyCode::Tab, .. }) => {
+            if kind == KeyEventKind::Press {
+                app.complete();
+            }
+        }
+        Event::Key(KeyEvent { code: KeyCode::BackTab, .. }) => {
             // nothing to do, just need to intercept this from text area edit
         }
         ev => {
-            app.query_editor.input(ev);
+            if app.query_editor.input(ev) {
+                app.query_edited();
+            }
         }
     };
 
     Ok(())
 }
+
+/// While the result viewer has focus it gets the keys first, and Esc goes back to the query rather than quitting.
+/// Up and down move its cursor rather than scroll it
+/// Returns false if the key should be handled as though the query editor had focus
+fn handle_result_event(app: &mut App, ev: &Event) -> bool {
+    let &Event::Key(KeyEvent { kind, code, modifiers, .. }) = ev else {
+        return false;
+    };
+    if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) {
+        return false;
+    }
+    if kind != KeyEventKind::Press {
+        return true;
+    }
+    match code {
+        // the function keys do the same wherever the focus is
+        KeyCode::F(_) => return false,
+        KeyCode::Esc => app.leave_result(),
+        KeyCode::Up => app.move_result_cursor(-1),
+        KeyCode::Down => app.move_result_cursor(1),
+        KeyCode::PageUp => app.move_result_cursor_pages(-1),
+        KeyCode::PageDown => app.move_result_cursor_pages(1),
+        KeyCode::Home => app.result_cursor_to_start(),
+        KeyCode::End => app.result_cursor_to_end(),
+        KeyCode::Char('v') => app.toggle_selection(),
+        KeyCode::Char('t') => app.toggle_tree(),
+        KeyCode::Char('w') => app.toggle_wrap(),
+        KeyCode::Char('p') => app.pick_path(false),
+        KeyCode::Char('P') => app.pick_path(true),
+        code => {
+            let Some(tree) = app.shown_tree_mut() else {
+                // the tree folds on left and right, the text scrolls sideways
+                match code {
+                    KeyCode::Left => app.scroll_result_columns(-1),
+                    KeyCode::Right => app.scroll_result_columns(1),
+                    _ => {}
+                }
+                return true;
+            };
+            match code {
+                KeyCode::Left => tree.collapse(),
+                KeyCode::Right => tree.expand(),
+                KeyCode::Enter | KeyCode::Char(' ') => tree.toggle(),
+                KeyCode::Char('*') => tree.expand_all(),
+                KeyCode::Char(digit @ '0'..='9') => tree.collapse_to_depth(digit as usize - '0' as usize),
+                _ => {}
+            }
+        }
+    }
+    true
+}
+
+/// While a popup is open it gets the keys first, and Esc closes it rather than quitting.
+/// Returns false if the key should be handled as though the popup was not there
+fn handle_popup_event(app: &mut App, ev: &Event) -> bool {
+    let &Event::Key(KeyEvent { kind: KeyEventKind::Press, code, modifiers, .. }) = ev else {
+        return true;
+    };
+    match app.popup.as_mut() {
+        Some(Popup::HistorySearch(search)) => match code {
+            KeyCode::Esc => app.popup = None,
+            KeyCode::Enter => app.accept_history_search(),
+            KeyCode::Up => search.select_previous(),
+            KeyCode::Down => search.select_next(),
+            KeyCode::Char('/') if modifiers.contains(KeyModifiers::ALT) => search.select_next(),
+            KeyCode::Backspace => search.pop_char(),
+            KeyCode::Char(ch) if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => search.push_char(ch),
+            _ => {}
+        },
+        Some(Popup::SaveQuery(form)) => match code {
+            KeyCode::Esc => app.popup = None,
+            KeyCode::Enter => app.accept_save_form(),
+            KeyCode::Tab | KeyCode::BackTab => form.switch_field(),
+            KeyCode::Backspace => form.pop_char(),
+            KeyCode::Char(ch) if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => form.push_char(ch),
+            _ => {}
+        },
+        Some(Popup::SavedPicker(picker)) => match code {
+            KeyCode::Esc => app.popup = None,
+            KeyCode::Enter => app.accept_saved_picker(),
+            KeyCode::Up => picker.select_previous(),
+            KeyCode::Down => picker.select_next(),
+            KeyCode::Delete => app.delete_selected_saved(),
+            KeyCode::Backspace => {
+                picker.needle.pop();
+                picker.refilter(&app.saved);
+            }
+            KeyCode::Char(ch) if !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                picker.needle.push(ch);
+                picker.refilter(&app.saved);
+            }
+            _ => {}
+        },
+        Some(Popup::ConfirmWrite(confirm)) => match code {
+            KeyCode::Esc | KeyCode::Char('n') => app.popup = None,
+            KeyCode::Enter | KeyCode::Char('y') => app.confirm_write(),
+            KeyCode::Up => confirm.diff.text.scroll_up(),
+            KeyCode::Down => confirm.diff.text.scroll_down(),
+            _ => {}
+        },
+        Some(Popup::Completion(completion)) => match code {
+            KeyCode::Esc => app.popup = None,
+            KeyCode::Enter => app.accept_completion(),
+            KeyCode::Tab | KeyCode::Down => completion.select_next(),
+            KeyCode::BackTab | KeyCode::Up => completion.select_previous(),
+            _ => {
+                // carry on typing, the completions would be stale now
+                app.popup = None;
+                return false;
+            }
+        },
+        None => {}
+    }
+    true
+}
@@ -1,88 +1,506 @@
 use std::sync::mpsc::{
-    channel, 
+    channel,
     Receiver,
+    Sender,
     TryRecvError
 };
-use std::thread;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::sync::{
+    atomic::{AtomicBool, Ordering as AtomicOrdering},
+    Arc,
+    Mutex,
+    OnceLock
+};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 
 use anyhow::Result;
+use clap::ValueEnum;
 use subprocess::{
     ExitStatus, Popen, PopenConfig, Redirection
 };
 
-const JQ_EXE_NAME: &str = "jq";
+use crate::{
+    backend::{ErrorLocation, FilterBackend},
+    cli::Cli,
+    eval,
+    json::{self, JsonData, JsonDataType}
+};
+
+/// How long the worker waits on the child before letting go of it, so that it can be killed
+const WAIT_SLICE: Duration = Duration::from_millis(50);
+
+/// How much of jq's output we read at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The command line options that we forward to jq
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JqOptions {
+    /// `-r`
+    pub raw_output: bool,
+    /// `-s`
+    pub slurp: bool,
+    /// `-c`
+    pub compact_output: bool,
+    /// `-S`
+    pub sort_keys: bool,
+    /// `-n`
+    pub null_input: bool,
+    /// `--arg name value`
+    pub args: Vec<(String, String)>,
+    /// `--argjson name json`
+    pub json_args: Vec<(String, String)>,
+    /// `--slurpfile name file`
+    pub slurp_files: Vec<(String, String)>,
+}
+
+impl JqOptions {
+    pub fn from_cli(cli: &Cli) -> Self {
+        Self {
+            raw_output: cli.raw_output,
+            slurp: cli.slurp,
+            compact_output: cli.compact_output,
+            sort_keys: cli.sort_keys,
+            null_input: cli.null_input,
+            args: pairs(&cli.args),
+            json_args: pairs(&cli.json_args),
+            slurp_files: pairs(&cli.slurp_files),
+        }
+    }
+
+    /// The short flags that are switched on, e.g. `["-r", "-S"]`
+    pub fn flags(&self) -> Vec<&'static str> {
+        [
+            (self.raw_output, "-r"),
+            (self.slurp, "-s"),
+            (self.compact_output, "-c"),
+            (self.sort_keys, "-S"),
+            (self.null_input, "-n"),
+        ]
+            .into_iter()
+            .filter_map(|(on, flag)| on.then_some(flag))
+            .collect()
+    }
+
+    /// Everything that goes on the jq command line before the query
+    pub fn to_args(&self) -> Vec<String> {
+        let mut argv: Vec<String> = self.flags()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+
+        let named = [
+            ("--arg", &self.args),
+            ("--argjson", &self.json_args),
+            ("--slurpfile", &self.slurp_files),
+        ];
+        for (opt, bindings) in named {
+            for (name, value) in bindings {
+                argv.push(opt.to_string());
+                argv.push(name.clone());
+                argv.push(value.clone());
+            }
+        }
+
+        argv
+    }
+}
+
+/// clap hands us `--arg a 1 --arg b 2` as `[a, 1, b, 2]`
+fn pairs(flat: &[String]) -> Vec<(String, String)> {
+    flat.chunks_exact(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect()
+}
+
+/// Who gets to run a query
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Engine {
+    /// The built in evaluator when it supports the query, otherwise the backend
+    Auto,
+    /// Only the built in evaluator
+    Native,
+    /// Only the backend
+    External,
+}
+
+/// The input as parsed for the native evaluator: every value in it, in one array like jq's -s would read them.
+/// Holds the reason it could not be parsed, if it could not
+type ParsedInput = Result<JsonData<'static>, String>;
+
+fn parse_input(source: &'static str) -> ParsedInput {
+    json::loads_all(source)
+        .map(JsonData::array)
+        .map_err(|e| {
+            log::info!("the input can not be evaluated natively: {e}");
+            e.to_string()
+        })
+}
 
 #[derive(Debug)]
 pub struct JqClient {
-    maybe_job: Option<JqJob>
+    /// The program that runs the queries
+    pub backend: FilterBackend,
+    pub engine: Engine,
+    /// True if the last query was answered by the built in evaluator
+    pub answered_natively: bool,
+    /// The input, read once and shared with the jobs that evaluate over it
+    source: &'static str,
+    /// The input as parsed for the built in evaluator, filled in by whichever thread needs it first
+    native_input: Arc<OnceLock<ParsedInput>>,
+    /// True once a thread has been started to parse the input in the background
+    parsing_input: bool,
+    /// Output that was produced without a job, waiting to be picked up
+    ready: Option<JqOutput>,
+    maybe_job: Option<JqJob>,
+    /// The id to hand out to the next job we submit
+    next_job_id: u64,
+    /// How long a job may run before it is killed. `None` means no limit.
+    timeout: Option<Duration>,
 }
 impl JqClient {
-    pub fn new() -> Self {
+    pub fn new(backend: FilterBackend, engine: Engine, timeout: Option<Duration>, source: &'static str) -> Self {
         Self {
-            maybe_job: None
+            backend,
+            engine,
+            answered_natively: false,
+            source,
+            native_input: Arc::new(OnceLock::new()),
+            parsing_input: false,
+            ready: None,
+            maybe_job: None,
+            next_job_id: 0,
+            timeout,
         }
     }
-    /// Submits a new query, overwriting any previous job that we might have had 
-    pub fn submit_query(&mut self, source: &'static str, query: String) {
-        self.maybe_job = Some(JqJob::new(source, query));
+    /// Submits a new query, overwriting any previous job that we might have had.
+    /// The previous job is superseded: its subprocess is killed and whatever it produced is never handed back.
+    pub fn submit_query(&mut self, query: String, options: &JqOptions) {
+        let id = self.next_job_id;
+        self.next_job_id += 1;
+
+        if let Some(old) = &self.maybe_job {
+            log::info!("job {} is superseded by job {id}", old.id);
+        }
+        self.maybe_job = None;
+        self.ready = None;
+        self.answered_natively = false;
+
+        // only the syntax is checked here, the evaluation itself runs in the job like the backend would
+        let native = match self.engine {
+            Engine::External => None,
+            _ => match eval::compile(&query, options) {
+                Ok(program) => Some(NativeRun {
+                    program,
+                    input: Arc::clone(&self.native_input),
+                    fallback: self.engine == Engine::Auto,
+                }),
+                Err(e) if self.engine == Engine::Native => {
+                    self.ready = Some(JqOutput::Failure {
+                        title: "not supported by the native evaluator".to_string(),
+                        failure: e.to_string(),
+                        location: None,
+                    });
+                    return;
+                }
+                Err(e) => {
+                    log::info!("falling back to {} for query {id}: {e}", self.backend.name());
+                    None
+                }
+            },
+        };
+
+        self.maybe_job = Some(JqJob::new(id, &self.backend, self.source, query, options, native));
     }
-    /// Returns the output of the last ran job, if it has completed. Otherwise, `None`.
+    /// The input as `.` prints it with `options`, by the native evaluator if it answered the last query and by the backend
+    /// otherwise, so that it can be compared with the output. Waits for it. `None` if it could not be formatted
+    pub fn format_input(&self, options: &JqOptions) -> Option<String> {
+        if self.answered_natively {
+            return eval::run(".", self.wait_for_inputs()?, options).ok();
+        }
+        self.backend.format(self.source, options)
+    }
+    /// Every value of the input in one array, parsed for the native evaluator. Waits for it to be parsed if it is not yet.
+    /// `None` if it does not parse
+    pub fn wait_for_inputs(&self) -> Option<&JsonData<'static>> {
+        let source = self.source;
+        self.native_input.get_or_init(|| parse_input(source)).as_ref().ok()
+    }
+    /// The input as `.` sees it: the array of every value with -s, otherwise the one value there is.
+    /// `None` if it does not parse, or holds more than one value without -s
+    pub fn wait_for_input(&self, slurp: bool) -> Option<&JsonData<'static>> {
+        input_as_seen(self.wait_for_inputs()?, slurp)
+    }
+    /// Like `wait_for_input`, but never waits. `None` while the input is still being parsed in the background,
+    /// which this starts if nothing has yet
+    pub fn parsed_input(&mut self, slurp: bool) -> Option<&JsonData<'static>> {
+        if !self.parsing_input && self.native_input.get().is_none() {
+            self.parsing_input = true;
+            let input = Arc::clone(&self.native_input);
+            let source = self.source;
+            thread::spawn(move || {
+                input.get_or_init(|| parse_input(source));
+            });
+        }
+        input_as_seen(self.native_input.get()?.as_ref().ok()?, slurp)
+    }
+    /// True once the input has been parsed, or found not to parse
+    pub fn input_is_parsed(&self) -> bool {
+        self.native_input.get().is_some()
+    }
+    /// Kills the running job, if there is one. Returns true if something was cancelled.
+    pub fn cancel(&mut self) -> bool {
+        match self.maybe_job.take() {
+            Some(job) => {
+                log::info!("cancelling job {}", job.id);
+                true
+            }
+            None => false,
+        }
+    }
+    /// Returns the next output of the last ran job, if there is one. Otherwise, `None`.
     pub fn try_recv_output(&mut self) -> Option<JqOutput> {
+        if let Some(output) = self.ready.take() {
+            return Some(output);
+        }
         let Some(job) = &self.maybe_job else { return None; };
-        let Some(output) = job.output() else { return None; };
 
-        self.maybe_job = None;
+        if let Some(timeout) = self.timeout {
+            if job.started.elapsed() > timeout {
+                log::warn!("job {} timed out after {timeout:?}", job.id);
+                // dropping the job kills the subprocess, or stops the evaluation
+                self.maybe_job = None;
+                return Some(JqOutput::Failure {
+                    title: format!("timed out after {}s", timeout.as_secs()),
+                    failure: format!("the query did not finish within {}s and was stopped", timeout.as_secs()),
+                    location: None,
+                });
+            }
+        }
+
+        let output = job.output()?;
+        self.answered_natively = job.answered_natively.load(AtomicOrdering::Relaxed);
+
+        if !matches!(output, JqOutput::Partial { .. }) {
+            log::info!("job {} finished", job.id);
+            self.maybe_job = None;
+        }
         Some(output)
     }
+    /// True while a job is running in the background
+    pub fn is_running(&self) -> bool {
+        self.maybe_job.is_some()
+    }
+}
+
+/// The input as `.` sees it, from every value of the input in an array
+fn input_as_seen<'a>(inputs: &'a JsonData<'static>, slurp: bool) -> Option<&'a JsonData<'static>> {
+    match (slurp, inputs.ty()) {
+        (true, _) => Some(inputs),
+        (false, JsonDataType::Array { elems }) if elems.len() == 1 => elems.first(),
+        _ => None,
+    }
+}
+
+/// What a job needs to try the built in evaluator before the backend
+#[derive(Debug)]
+struct NativeRun {
+    program: eval::Program,
+    input: Arc<OnceLock<ParsedInput>>,
+    /// Whether to hand the query to the backend if it can not be evaluated natively, rather than fail
+    fallback: bool,
 }
 
 #[derive(Debug)]
 pub struct JqJob {
-    rx: Receiver<JqOutput>
+    id: u64,
+    rx: Receiver<JqOutput>,
+    /// The jq subprocess, shared with the worker thread. `None` until the worker starts it, and for good
+    /// if the query is answered natively or jq could not be started
+    child: Arc<Mutex<Option<Popen>>>,
+    /// Set when the job is dropped, so that the worker stops evaluating and does not start jq after all
+    cancelled: Arc<AtomicBool>,
+    /// Set by the worker if the built in evaluator answered the query
+    answered_natively: Arc<AtomicBool>,
+    started: Instant,
 }
 
 impl JqJob {
-    pub fn new(source: &'static str, query: String) -> JqJob {
+    fn new(id: u64, backend: &FilterBackend, source: &'static str, query: String, options: &JqOptions, native: Option<NativeRun>) -> JqJob {
         let (tx, rx) = channel();
+        let child = Arc::new(Mutex::new(None));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let answered_natively = Arc::new(AtomicBool::new(false));
+
+        let worker = Worker {
+            backend: backend.clone(),
+            source,
+            query,
+            options: options.clone(),
+            child: Arc::clone(&child),
+            cancelled: Arc::clone(&cancelled),
+            tx,
+        };
+        let worker_answered_natively = Arc::clone(&answered_natively);
         thread::spawn(move || {
             log::info!("spawning jq worker thread");
-            let result = apply_filter(source, query);
-            let out = match result {
-                Ok(out) => out,
-                Err(e) => {
-                    log::error!("jq worker exitted with error: {e}");
-                    JqOutput::Failure {
-                        title: format!("fault"),
-                        failure: format!("jq worker exitted with error: {e}")
+            let out = match native {
+                Some(native) => {
+                    let input = native.input.get_or_init(|| parse_input(source));
+                    let result = match input {
+                        Ok(input) => native.program.run(input, &worker.cancelled),
+                        Err(e) => Err(anyhow::anyhow!("the input could not be parsed: {e}")),
+                    };
+                    match result {
+                        Ok(json_content) => {
+                            log::info!("query {id} answered by the native evaluator");
+                            worker_answered_natively.store(true, AtomicOrdering::Relaxed);
+                            JqOutput::Success { json_content }
+                        }
+                        Err(e) if !native.fallback => {
+                            let failure = e.to_string();
+                            JqOutput::Failure {
+                                title: failure.lines().next().unwrap_or("error").to_string(),
+                                failure,
+                                location: None,
+                            }
+                        }
+                        Err(e) => {
+                            log::info!("falling back to {} for query {id}: {e}", worker.backend.name());
+                            worker.run_backend()
+                        }
                     }
                 }
+                None => worker.run_backend(),
             };
-            match tx.send(out) {
-                Ok(_) => {},
-                Err(e) => {
-                    log::error!("could not send jq output: {e}");
-                }
-            }
+            send_output(&worker.tx, out);
         });
-        JqJob { rx }
+        JqJob { id, rx, child, cancelled, answered_natively, started: Instant::now() }
     }
     /// Get the output of the command, if it is ready
     pub fn output(&self) -> Option<JqOutput> {
         match self.rx.try_recv() {
             Ok(out) => Some(out),
             Err(TryRecvError::Disconnected) => Some(JqOutput::Failure {
-                title: format!("fault"),
-                failure: format!("channel to jq worker thread disconnected")
+                title: "fault".to_string(),
+                failure: "channel to jq worker thread disconnected".to_string(),
+                location: None,
             }),
             Err(TryRecvError::Empty) => None,
         }
 
     }
+    /// Stop the worker, and kill the subprocess if it is still running
+    fn kill(&self) {
+        // set before taking the lock, so that a worker that takes it after us sees it and does not start jq
+        self.cancelled.store(true, AtomicOrdering::Relaxed);
+        let mut child = match self.child.lock() {
+            Ok(child) => child,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let Some(child) = child.as_mut() else { return; };
+        if child.poll().is_none() {
+            log::info!("killing jq subprocess of job {}", self.id);
+            if let Err(e) = child.kill() {
+                log::error!("could not kill jq subprocess: {e}");
+            }
+        }
+    }
+}
+
+impl Drop for JqJob {
+    fn drop(&mut self) {
+        self.kill();
+    }
+}
+
+/// Everything the worker thread of a job needs to run the query on the backend
+struct Worker {
+    backend: FilterBackend,
+    source: &'static str,
+    query: String,
+    options: JqOptions,
+    child: Arc<Mutex<Option<Popen>>>,
+    cancelled: Arc<AtomicBool>,
+    tx: Sender<JqOutput>,
+}
+
+impl Worker {
+    /// Starts jq and streams its output, returning the final output once it exits
+    fn run_backend(&self) -> JqOutput {
+        let (stdout, stderr) = {
+            let mut slot = match self.child.lock() {
+                Ok(slot) => slot,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            if self.cancelled.load(AtomicOrdering::Relaxed) {
+                return JqOutput::Failure {
+                    title: "cancelled".to_string(),
+                    failure: "the job was dropped before jq was started".to_string(),
+                    location: None,
+                };
+            }
+            let mut process = match spawn_jq(self.backend.command(&self.query, &self.options)) {
+                Ok(process) => process,
+                Err(e) => {
+                    log::error!("could not start {}: {e}", self.backend.exe.display());
+                    return JqOutput::Failure {
+                        title: "fault".to_string(),
+                        failure: format!("could not start {}: {e}", self.backend.exe.display()),
+                        location: None,
+                    };
+                }
+            };
+
+            // take the pipes out of the process, so that we do not have to hold the lock while reading
+            let stdin = process.stdin.take();
+            let stdout = process.stdout.take();
+            let stderr = process.stderr.take();
+            *slot = Some(process);
+
+            // feed the input from its own thread, so that neither side can block the other
+            if let Some(mut stdin) = stdin {
+                let source = self.source;
+                thread::spawn(move || {
+                    if let Err(e) = stdin.write_all(source.as_bytes()) {
+                        // jq is allowed to stop reading early, e.g. on a compile error
+                        log::info!("stopped writing input to jq: {e}");
+                    }
+                });
+            }
+            let stderr = stderr.map(|mut stderr| thread::spawn(move || {
+                let mut buf = Vec::new();
+                if let Err(e) = stderr.read_to_end(&mut buf) {
+                    log::error!("could not read jq stderr: {e}");
+                }
+                String::from_utf8_lossy(&buf).into_owned()
+            }));
+            (stdout, stderr)
+        };
+
+        match stream_output(&self.backend, &self.query, stdout, stderr, &self.child, &self.tx) {
+            Ok(out) => out,
+            Err(e) => {
+                log::error!("jq worker exitted with error: {e}");
+                JqOutput::Failure {
+                    title: "fault".to_string(),
+                    failure: format!("jq worker exitted with error: {e}"),
+                    location: None,
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum JqOutput {
+    /// Jq is still running, and this is the next piece of its output.
+    Partial {
+        json_content: String
+    },
     /// Jq ran successfully and we have some new content to show the user.
+    /// If there were `Partial` outputs before this, it holds only the rest of the content.
     Success {
         json_content: String
     },
@@ -90,12 +508,23 @@ pub enum JqOutput {
     Failure {
         title: String,
         failure: String,
+        /// Where in the query the problem is, if the backend told us
+        location: Option<ErrorLocation>,
     },
 }
 
-fn apply_filter(source: &'static str, query: String) -> Result<JqOutput> {
-    let mut process = Popen::create(
-        &[JQ_EXE_NAME, query.as_str()],
+fn send_output(tx: &Sender<JqOutput>, out: JqOutput) {
+    if tx.send(out).is_err() {
+        // the job was dropped (superseded, cancelled or timed out), nobody wants this anymore
+        log::info!("jq output discarded, the job is gone");
+    }
+}
+
+fn spawn_jq(argv: Vec<String>) -> Result<Popen> {
+    log::info!("running {argv:?}");
+
+    let process = Popen::create(
+        &argv,
         PopenConfig {
             stdin: Redirection::Pipe,
             stdout: Redirection::Pipe,
@@ -103,14 +532,65 @@ fn apply_filter(source: &'static str, query: String) -> Result<JqOutput> {
             ..Default::default()
         }
     )?;
+    Ok(process)
+}
 
-    let (stdout, stderr) = process.communicate(Some(source))?;
-    let exit_status = process.wait()?;
+/// Reads the output of jq as it comes, sending it along in chunks of whole lines.
+/// Returns what is left to send once jq has exited.
+fn stream_output(
+    backend: &FilterBackend,
+    query: &str,
+    stdout: Option<File>,
+    stderr: Option<JoinHandle<String>>,
+    child: &Mutex<Option<Popen>>,
+    tx: &Sender<JqOutput>
+) -> Result<JqOutput> {
+    // the tail of the output that does not end in a newline yet
+    let mut pending = Vec::new();
+
+    if let Some(mut stdout) = stdout {
+        let mut buf = vec![0; CHUNK_SIZE];
+        loop {
+            let n = stdout.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            pending.extend_from_slice(&buf[..n]);
+
+            // only send whole lines, so that each chunk can be tokenized on its own
+            let Some(last_newline) = pending.iter().rposition(|&b| b == b'\n') else { continue; };
+            let rest = pending.split_off(last_newline + 1);
+            let chunk = String::from_utf8_lossy(&pending).into_owned();
+            pending = rest;
+
+            if tx.send(JqOutput::Partial { json_content: chunk }).is_err() {
+                log::info!("stopped reading jq output, the job is gone");
+                break;
+            }
+        }
+    }
+
+    // wait in slices, so that the lock is free for anyone who wants to kill the process
+    let exit_status = loop {
+        let mut child = match child.lock() {
+            Ok(child) => child,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let Some(child) = child.as_mut() else {
+            anyhow::bail!("the jq subprocess is gone");
+        };
+        if let Some(status) = child.wait_timeout(WAIT_SLICE)? {
+            break status;
+        }
+    };
 
     log::info!("jq exitted with {exit_status:?}");
 
-    let stdout = stdout.unwrap_or(format!("<missing stdout>"));
-    let stderr = stderr.unwrap_or(format!("<missing stderr>"));
+    let stdout = String::from_utf8_lossy(&pending).into_owned();
+    let stderr = match stderr.map(JoinHandle::join) {
+        Some(Ok(stderr)) => stderr,
+        _ => "<missing stderr>".to_string(),
+    };
 
     // translate the shell program's output
     let output = match exit_status {
@@ -118,26 +598,156 @@ fn apply_filter(source: &'static str, query: String) -> Result<JqOutput> {
             0 => JqOutput::Success {
                 json_content: stdout
             },
-            _ => JqOutput::Failure {
-                title: format!("jq subprocess exited with exit code {rc}"),
-                failure: stderr,
+            _ => match backend.parse_error(&stderr, query) {
+                Some(error) => JqOutput::Failure {
+                    title: error.summary,
+                    failure: stderr,
+                    location: error.location,
+                },
+                None => JqOutput::Failure {
+                    title: format!("{} subprocess exited with exit code {rc}", backend.name()),
+                    failure: stderr,
+                    location: None,
+                },
             }
         },
         ExitStatus::Signaled(x) => JqOutput::Failure {
-            title: format!("fault"),
-            failure: format!("the jq subprocess exited due to a signal {x}")
+            title: "fault".to_string(),
+            failure: format!("the {} subprocess exited due to a signal {x}", backend.name()),
+            location: None,
         },
         ExitStatus::Other(x) => JqOutput::Failure {
-            title: format!("fault"),
-            failure: format!("This should not occur. The jq subprocess exited (other - {x})"),
+            title: "fault".to_string(),
+            failure: format!("This should not occur. The {} subprocess exited (other - {x})", backend.name()),
+            location: None,
         },
         ExitStatus::Undetermined => JqOutput::Failure {
-            title: format!("fault"),
-            failure: format!("undetermined exit status of jq subprocess")
+            title: "fault".to_string(),
+            failure: format!("undetermined exit status of {} subprocess", backend.name()),
+            location: None,
         },
     };
 
     Ok(output)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn options_to_args_empty() {
+        let options = JqOptions::default();
+        assert!(options.to_args().is_empty());
+    }
+
+    #[test]
+    fn options_to_args_flags_and_bindings() {
+        let options = JqOptions {
+            raw_output: true,
+            sort_keys: true,
+            args: vec![("x".to_string(), "1".to_string())],
+            json_args: vec![("y".to_string(), "{}".to_string())],
+            ..Default::default()
+        };
+        assert_eq!(options.to_args(), vec![
+            "-r", "-S",
+            "--arg", "x", "1",
+            "--argjson", "y", "{}",
+        ]);
+    }
+
+    /// Waits for the job to hand back its final output
+    fn final_output(client: &mut JqClient) -> JqOutput {
+        let started = Instant::now();
+        while started.elapsed() < Duration::from_secs(5) {
+            match client.try_recv_output() {
+                Some(JqOutput::Partial { .. }) | None => thread::sleep(Duration::from_millis(5)),
+                Some(output) => return output,
+            }
+        }
+        panic!("the job did not finish");
+    }
+
+    fn native_client(source: &'static str) -> JqClient {
+        let backend = FilterBackend {
+            kind: crate::backend::BackendKind::Jq,
+            exe: std::path::PathBuf::from("/nonexistent/jq"),
+            version: None,
+            builtins: Vec::new(),
+        };
+        JqClient::new(backend, Engine::Native, None, source)
+    }
+
+    #[test]
+    fn native_queries_run_in_the_job() {
+        let mut client = native_client(r#"{"a": [1, 2]}"#);
+        client.submit_query(".a[]".to_string(), &JqOptions::default());
+        assert!(client.is_running());
+        match final_output(&mut client) {
+            JqOutput::Success { json_content } => assert_eq!(json_content, "1\n2\n"),
+            other => panic!("expected a success, got {other:?}"),
+        }
+        assert!(client.answered_natively);
+    }
+
+    #[test]
+    fn native_queries_follow_slurp() {
+        let mut client = native_client("1 2");
+        let slurp = JqOptions {
+            slurp: true,
+            compact_output: true,
+            ..Default::default()
+        };
+        client.submit_query(".".to_string(), &slurp);
+        match final_output(&mut client) {
+            JqOutput::Success { json_content } => assert_eq!(json_content, "[1,2]\n"),
+            other => panic!("expected a success, got {other:?}"),
+        }
+        client.submit_query(".".to_string(), &JqOptions::default());
+        match final_output(&mut client) {
+            JqOutput::Success { json_content } => assert_eq!(json_content, "1\n2\n"),
+            other => panic!("expected a success, got {other:?}"),
+        }
+        assert_eq!(client.wait_for_input(false), None);
+    }
 
+    #[test]
+    fn native_errors_are_titled_by_what_went_wrong() {
+        let mut client = native_client("[1]");
+
+        client.submit_query(".a |= 1".to_string(), &JqOptions::default());
+        match final_output(&mut client) {
+            JqOutput::Failure { title, .. } => assert_eq!(title, "not supported by the native evaluator"),
+            other => panic!("expected a failure, got {other:?}"),
+        }
+
+        client.submit_query(".a".to_string(), &JqOptions::default());
+        match final_output(&mut client) {
+            JqOutput::Failure { title, .. } => assert_eq!(title, "Cannot index array with \"a\""),
+            other => panic!("expected a failure, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn input_is_formatted_by_what_answered_the_last_query() {
+        let mut client = native_client(r#"{"b": 1, "a": 2}"#);
+        let options = JqOptions {
+            sort_keys: true,
+            compact_output: true,
+            ..Default::default()
+        };
+        // nothing has answered yet, so it goes to the backend, which is not there
+        assert_eq!(client.format_input(&options), None);
+
+        client.submit_query(".".to_string(), &options);
+        final_output(&mut client);
+        assert_eq!(client.format_input(&options).as_deref(), Some("{\"a\":2,\"b\":1}\n"));
+    }
+
+    #[test]
+    fn pairs_ignores_dangling_value() {
+        let flat = vec!["a".to_string(), "1".to_string(), "b".to_string()];
+        assert_eq!(pairs(&flat), vec![("a".to_string(), "1".to_string())]);
+    }
+}
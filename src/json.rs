@@ -2,7 +2,10 @@ use anyhow::{
     bail, Result
 };
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet}
+};
 
 use crate::tokens::{self, Token, TokenType};
 
@@ -21,6 +24,7 @@ pub enum JsonDataType<'a> {
     Str { lex: Cow<'a, str> },
     Boolean { lex: Cow<'a, str> },
     Number { lex: Cow<'a, str> },
+    Null,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -28,6 +32,495 @@ pub struct JsonKey<'a> {
     lex: Cow<'a, str>,
 }
 
+impl <'a> JsonData<'a> {
+    pub fn new(ty: JsonDataType<'a>) -> JsonData<'a> {
+        JsonData(JsonDataInner { ty })
+    }
+    pub fn null() -> JsonData<'a> {
+        JsonData::new(JsonDataType::Null)
+    }
+    pub fn boolean(b: bool) -> JsonData<'a> {
+        let lex = if b { "true" } else { "false" };
+        JsonData::new(JsonDataType::Boolean { lex: Cow::Borrowed(lex) })
+    }
+    pub fn number(n: f64) -> JsonData<'a> {
+        JsonData::new(JsonDataType::Number { lex: Cow::Owned(format_number(n)) })
+    }
+    pub fn string(s: &str) -> JsonData<'a> {
+        JsonData::new(JsonDataType::Str { lex: Cow::Owned(quote(s)) })
+    }
+    pub fn array(elems: Vec<JsonData<'a>>) -> JsonData<'a> {
+        JsonData::new(JsonDataType::Array { elems })
+    }
+    pub fn object(entries: Vec<(JsonKey<'a>, JsonData<'a>)>) -> JsonData<'a> {
+        JsonData::new(JsonDataType::Object { entries })
+    }
+
+    pub fn ty(&self) -> &JsonDataType<'a> {
+        &self.0.ty
+    }
+
+    /// The name jq uses for the type of this value, as in `jq type`
+    pub fn type_name(&self) -> &'static str {
+        match self.ty() {
+            JsonDataType::Object { .. } => "object",
+            JsonDataType::Array { .. } => "array",
+            JsonDataType::Str { .. } => "string",
+            JsonDataType::Boolean { .. } => "boolean",
+            JsonDataType::Number { .. } => "number",
+            JsonDataType::Null => "null",
+        }
+    }
+
+    /// The value of a number. `None` if this is not a number
+    pub fn as_f64(&self) -> Option<f64> {
+        match self.ty() {
+            JsonDataType::Number { lex } => lex.parse().ok(),
+            _ => None,
+        }
+    }
+
+    /// The (unescaped) contents of a string. `None` if this is not a string
+    pub fn as_str(&self) -> Option<Cow<'_, str>> {
+        match self.ty() {
+            JsonDataType::Str { lex } => Some(unescape(lex)),
+            _ => None,
+        }
+    }
+
+    /// Everything except `false` and `null` is true
+    pub fn is_truthy(&self) -> bool {
+        match self.ty() {
+            JsonDataType::Null => false,
+            JsonDataType::Boolean { lex } => lex == "true",
+            _ => true,
+        }
+    }
+}
+
+impl <'a> JsonKey<'a> {
+    pub fn new(name: &str) -> JsonKey<'a> {
+        JsonKey { lex: Cow::Owned(quote(name)) }
+    }
+    /// The key with the quotes and escapes removed
+    pub fn name(&self) -> Cow<'_, str> {
+        unescape(&self.lex)
+    }
+}
+
+/// Formats a number the way jq prints it: the shortest digits that read back as the same double,
+/// in exponent form (`1e+300`, `1e-05`) when they would need more than 15 padding zeros or start 4 places after the point
+pub fn format_number(n: f64) -> String {
+    if n.is_nan() {
+        return "null".to_string();
+    }
+    // jq clamps the infinities to the largest finite doubles
+    let n = n.clamp(f64::MIN, f64::MAX);
+
+    let sign = if n.is_sign_negative() { "-" } else { "" };
+    // rust gives the shortest round tripping digits as `d.ddde<exp>`
+    let sci = format!("{:e}", n.abs());
+    let (mantissa, exp) = sci.split_once('e').unwrap_or((&sci, "0"));
+    let digits = mantissa.replace('.', "");
+    let exp: i32 = exp.parse().unwrap_or(0);
+    // where the decimal point goes, counted in digits from the left
+    let point = exp + 1;
+
+    if point <= -4 || point > digits.len() as i32 + 15 {
+        let (first, rest) = digits.split_at(1);
+        let dot = if rest.is_empty() { "" } else { "." };
+        let exp_sign = if exp < 0 { '-' } else { '+' };
+        format!("{sign}{first}{dot}{rest}e{exp_sign}{:02}", exp.abs())
+    } else if point <= 0 {
+        format!("{sign}0.{}{digits}", "0".repeat(point.unsigned_abs() as usize))
+    } else if point as usize >= digits.len() {
+        format!("{sign}{digits}{}", "0".repeat(point as usize - digits.len()))
+    } else {
+        let (whole, fract) = digits.split_at(point as usize);
+        format!("{sign}{whole}.{fract}")
+    }
+}
+
+/// How jq prints a number however it was written in the input, e.g. `1.0` is `1` and `1E2` is `100`
+fn normalize_number(lex: &str) -> Cow<'_, str> {
+    match lex.parse::<f64>() {
+        Ok(n) => Cow::Owned(format_number(n)),
+        Err(_) => Cow::Borrowed(lex),
+    }
+}
+
+/// jq keeps one entry per key: the last value given for it, in the place where the key first appeared
+fn dedup_keys<'a>(entries: Vec<(JsonKey<'a>, JsonData<'a>)>) -> Vec<(JsonKey<'a>, JsonData<'a>)> {
+    let has_duplicates = {
+        let mut seen = HashSet::with_capacity(entries.len());
+        !entries.iter().all(|(key, _)| seen.insert(key.name()))
+    };
+    if !has_duplicates {
+        return entries;
+    }
+    let mut deduped: Vec<(JsonKey<'a>, JsonData<'a>)> = Vec::with_capacity(entries.len());
+    let mut position: HashMap<String, usize> = HashMap::with_capacity(entries.len());
+    for (key, value) in entries {
+        match position.get(key.name().as_ref()) {
+            Some(&i) => deduped[i].1 = value,
+            None => {
+                position.insert(key.name().into_owned(), deduped.len());
+                deduped.push((key, value));
+            }
+        }
+    }
+    deduped
+}
+
+/// Turns a string lexeme (quotes included) into the string it stands for
+pub fn unescape(lex: &str) -> Cow<'_, str> {
+    let inner = lex.strip_prefix('"')
+        .and_then(|l| l.strip_suffix('"'))
+        .unwrap_or(lex);
+
+    if !inner.contains('\\') {
+        return Cow::Borrowed(inner);
+    }
+
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            out.push(ch);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('b') => out.push('\u{8}'),
+            Some('f') => out.push('\u{c}'),
+            Some('u') => {
+                let hi = read_hex4(&mut chars);
+                // surrogate pairs come as two escapes in a row
+                let code = if (0xD800..0xDC00).contains(&hi) && chars.as_str().starts_with("\\u") {
+                    // skip the `\u`
+                    chars.nth(1);
+                    let lo = read_hex4(&mut chars);
+                    0x10000 + ((hi - 0xD800) << 10) + (lo.wrapping_sub(0xDC00) & 0x3FF)
+                } else {
+                    hi
+                };
+                out.push(char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER));
+            }
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    Cow::Owned(out)
+}
+
+fn read_hex4(chars: &mut std::str::Chars) -> u32 {
+    let digits: String = chars.by_ref().take(4).collect();
+    u32::from_str_radix(&digits, 16).unwrap_or(0xFFFD)
+}
+
+/// Turns a string into a json string lexeme, escaping it the same way jq does
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\u{8}' => out.push_str("\\b"),
+            '\u{c}' => out.push_str("\\f"),
+            ch if (ch as u32) < 0x20 || ch == '\u{7f}' => {
+                out.push_str(&format!("\\u{:04x}", ch as u32));
+            }
+            ch => out.push(ch),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// How to lay out json when writing it back out
+#[derive(Debug, Copy, Clone, Default)]
+pub struct DumpOptions {
+    /// Everything on one line, like `jq -c`
+    pub compact: bool,
+    /// Sort the keys of objects, like `jq -S`
+    pub sort_keys: bool,
+}
+
+/// Writes the json out as text, formatted like jq formats its output
+pub fn dumps(json: &JsonData, options: DumpOptions) -> String {
+    let mut out = String::new();
+    dump_into(json, options, 0, &mut out);
+    out
+}
+
+fn dump_into(json: &JsonData, options: DumpOptions, depth: usize, out: &mut String) {
+    let newline = |out: &mut String, depth: usize| {
+        if !options.compact {
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+        }
+    };
+    match json.ty() {
+        JsonDataType::Object { entries } if entries.is_empty() => out.push_str("{}"),
+        JsonDataType::Object { entries } => {
+            let mut entries: Vec<_> = entries.iter().collect();
+            if options.sort_keys {
+                entries.sort_by(|(a, _), (b, _)| a.name().cmp(&b.name()));
+            }
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 1);
+                out.push_str(&quote(&key.name()));
+                out.push(':');
+                if !options.compact {
+                    out.push(' ');
+                }
+                dump_into(value, options, depth + 1, out);
+            }
+            newline(out, depth);
+            out.push('}');
+        }
+        JsonDataType::Array { elems } if elems.is_empty() => out.push_str("[]"),
+        JsonDataType::Array { elems } => {
+            out.push('[');
+            for (i, elem) in elems.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, depth + 1);
+                dump_into(elem, options, depth + 1, out);
+            }
+            newline(out, depth);
+            out.push(']');
+        }
+        JsonDataType::Str { lex } => out.push_str(&quote(&unescape(lex))),
+        JsonDataType::Number { lex } => out.push_str(&normalize_number(lex)),
+        JsonDataType::Boolean { lex } => out.push_str(lex),
+        JsonDataType::Null => out.push_str("null"),
+    }
+}
+
+
+/// One step down into a json value: the entry of an object, or the element of an array
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    Key(String),
+    Index(usize),
+}
+
+/// Writes a path the way jq would: `.a[0].b`, quoting keys that are not plain identifiers, as in `."a b"`.
+/// The empty path is `.`
+pub fn path_to_string(path: &[PathSegment]) -> String {
+    if path.is_empty() {
+        return ".".to_string();
+    }
+    let mut out = String::new();
+    for (i, segment) in path.iter().enumerate() {
+        match segment {
+            PathSegment::Key(key) if is_identifier(key) => {
+                out.push('.');
+                out.push_str(key);
+            }
+            PathSegment::Key(key) => {
+                out.push('.');
+                out.push_str(&quote(key));
+            }
+            PathSegment::Index(index) => {
+                if i == 0 {
+                    out.push('.');
+                }
+                out.push_str(&format!("[{index}]"));
+            }
+        }
+    }
+    out
+}
+
+/// Whether jq takes the key after a `.` without quotes
+fn is_identifier(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars.next().is_some_and(|ch| ch.is_ascii_alphabetic() || ch == '_')
+        && chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+/// A difference between two json values, at some path inside of them
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonChange<'a> {
+    Added { path: Vec<PathSegment>, value: &'a JsonData<'a> },
+    Removed { path: Vec<PathSegment>, value: &'a JsonData<'a> },
+    Changed { path: Vec<PathSegment>, old: &'a JsonData<'a>, new: &'a JsonData<'a> },
+}
+
+impl <'a> JsonChange<'a> {
+    pub fn path(&self) -> &[PathSegment] {
+        match self {
+            JsonChange::Added { path, .. } | JsonChange::Removed { path, .. } | JsonChange::Changed { path, .. } => path,
+        }
+    }
+}
+
+/// One line per change: `+ .path: value`, `- .path: value` or `~ .path: old -> new`
+impl <'a> std::fmt::Display for JsonChange<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let compact = DumpOptions { compact: true, sort_keys: false };
+        let path = path_to_string(self.path());
+        match self {
+            JsonChange::Added { value, .. } => write!(f, "+ {path}: {}", dumps(value, compact)),
+            JsonChange::Removed { value, .. } => write!(f, "- {path}: {}", dumps(value, compact)),
+            JsonChange::Changed { old, new, .. } => write!(f, "~ {path}: {} -> {}", dumps(old, compact), dumps(new, compact)),
+        }
+    }
+}
+
+/// Compares two json values by structure rather than by text: entries of objects are matched up by key,
+/// whatever order they come in, and elements of arrays by index.
+/// With a `match_key`, arrays whose elements are all objects with that key are matched up by its value instead
+pub fn diff<'a>(old: &'a JsonData<'a>, new: &'a JsonData<'a>, match_key: Option<&str>) -> Vec<JsonChange<'a>> {
+    let mut changes = Vec::new();
+    diff_into(old, new, match_key, &mut Vec::new(), &mut changes);
+    changes
+}
+
+fn diff_into<'a>(
+    old: &'a JsonData<'a>,
+    new: &'a JsonData<'a>,
+    match_key: Option<&str>,
+    path: &mut Vec<PathSegment>,
+    changes: &mut Vec<JsonChange<'a>>,
+) {
+    match (old.ty(), new.ty()) {
+        (JsonDataType::Object { entries: old_entries }, JsonDataType::Object { entries: new_entries }) => {
+            let new_by_key: HashMap<Cow<str>, &JsonData> = new_entries.iter()
+                .map(|(key, value)| (key.name(), value))
+                .collect();
+            let old_keys: HashSet<Cow<str>> = old_entries.iter().map(|(key, _)| key.name()).collect();
+
+            for (key, old_value) in old_entries {
+                path.push(PathSegment::Key(key.name().into_owned()));
+                match new_by_key.get(&key.name()) {
+                    Some(new_value) => diff_into(old_value, new_value, match_key, path, changes),
+                    None => changes.push(JsonChange::Removed { path: path.clone(), value: old_value }),
+                }
+                path.pop();
+            }
+            for (key, new_value) in new_entries {
+                if !old_keys.contains(&key.name()) {
+                    path.push(PathSegment::Key(key.name().into_owned()));
+                    changes.push(JsonChange::Added { path: path.clone(), value: new_value });
+                    path.pop();
+                }
+            }
+        }
+        (JsonDataType::Array { elems: old_elems }, JsonDataType::Array { elems: new_elems }) => {
+            let matched = match_key.is_some_and(|key| diff_arrays_by_key(old_elems, new_elems, key, path, changes));
+            if !matched {
+                diff_arrays_by_index(old_elems, new_elems, match_key, path, changes);
+            }
+        }
+        _ if same_value(old, new) => {}
+        _ => changes.push(JsonChange::Changed { path: path.clone(), old, new }),
+    }
+}
+
+fn diff_arrays_by_index<'a>(
+    old: &'a [JsonData<'a>],
+    new: &'a [JsonData<'a>],
+    match_key: Option<&str>,
+    path: &mut Vec<PathSegment>,
+    changes: &mut Vec<JsonChange<'a>>,
+) {
+    for (i, (old_elem, new_elem)) in old.iter().zip(new).enumerate() {
+        path.push(PathSegment::Index(i));
+        diff_into(old_elem, new_elem, match_key, path, changes);
+        path.pop();
+    }
+    for (i, old_elem) in old.iter().enumerate().skip(new.len()) {
+        path.push(PathSegment::Index(i));
+        changes.push(JsonChange::Removed { path: path.clone(), value: old_elem });
+        path.pop();
+    }
+    for (i, new_elem) in new.iter().enumerate().skip(old.len()) {
+        path.push(PathSegment::Index(i));
+        changes.push(JsonChange::Added { path: path.clone(), value: new_elem });
+        path.pop();
+    }
+}
+
+/// Matches up the elements of the arrays by the value they have for `key`.
+/// Gives up (returning false, having changed nothing) unless every element is an object with a distinct value for it.
+/// Removed elements are reported at their old index, everything else at the new one
+fn diff_arrays_by_key<'a>(
+    old: &'a [JsonData<'a>],
+    new: &'a [JsonData<'a>],
+    key: &str,
+    path: &mut Vec<PathSegment>,
+    changes: &mut Vec<JsonChange<'a>>,
+) -> bool {
+    let Some(old_ids) = element_ids(old, key) else { return false; };
+    let Some(new_ids) = element_ids(new, key) else { return false; };
+    let new_index: HashMap<&str, usize> = new_ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+    let old_index: HashMap<&str, usize> = old_ids.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+
+    for (i, id) in old_ids.iter().enumerate() {
+        match new_index.get(id.as_str()) {
+            Some(&j) => {
+                path.push(PathSegment::Index(j));
+                diff_into(&old[i], &new[j], Some(key), path, changes);
+                path.pop();
+            }
+            None => {
+                path.push(PathSegment::Index(i));
+                changes.push(JsonChange::Removed { path: path.clone(), value: &old[i] });
+                path.pop();
+            }
+        }
+    }
+    for (j, id) in new_ids.iter().enumerate() {
+        if !old_index.contains_key(id.as_str()) {
+            path.push(PathSegment::Index(j));
+            changes.push(JsonChange::Added { path: path.clone(), value: &new[j] });
+            path.pop();
+        }
+    }
+    true
+}
+
+/// The value each element has for `key`, written out so they can be compared. `None` if any element has no
+/// such key, or two share a value
+fn element_ids(elems: &[JsonData], key: &str) -> Option<Vec<String>> {
+    let compact = DumpOptions { compact: true, sort_keys: true };
+    let mut seen = HashSet::new();
+    let mut ids = Vec::with_capacity(elems.len());
+    for elem in elems {
+        let JsonDataType::Object { entries } = elem.ty() else { return None; };
+        let (_, value) = entries.iter().find(|(k, _)| k.name() == key)?;
+        let id = dumps(value, compact);
+        if !seen.insert(id.clone()) {
+            return None;
+        }
+        ids.push(id);
+    }
+    Some(ids)
+}
+
+/// Whether two scalars are the same, however they were written: `1.0` is `1`, and `"a"` is `"a"`
+fn same_value(a: &JsonData, b: &JsonData) -> bool {
+    match (a.ty(), b.ty()) {
+        (JsonDataType::Number { .. }, JsonDataType::Number { .. }) => a.as_f64() == b.as_f64(),
+        (JsonDataType::Str { .. }, JsonDataType::Str { .. }) => a.as_str() == b.as_str(),
+        _ => a == b,
+    }
+}
+
 
 struct ParsingContext<'a> {
     _source: &'a str,
@@ -82,6 +575,7 @@ impl <'a> ParsingContext<'a> {
             TokenType::String => self.parse_string()?,
             TokenType::Number => self.parse_number()?,
             TokenType::Boolean => self.parse_boolean()?,
+            TokenType::Null => self.parse_null()?,
             _ => { 
                 bail!("unexpected token type {tty:?}");
             }
@@ -95,6 +589,12 @@ impl <'a> ParsingContext<'a> {
 
         let mut entries = Vec::new();
 
+        self.eat_whitespace();
+        if self.peek().tty == TokenType::CloseBrace {
+            self.consume(TokenType::CloseBrace)?;
+            return Ok(JsonData::object(entries));
+        }
+
         loop {
             let key = self.parse_string()?;
             let _ = self.consume(TokenType::Colon)?;
@@ -126,7 +626,7 @@ impl <'a> ParsingContext<'a> {
 
         Ok(JsonData(JsonDataInner {
             ty: JsonDataType::Object {
-                entries
+                entries: dedup_keys(entries)
             },
         }))
 
@@ -138,6 +638,12 @@ impl <'a> ParsingContext<'a> {
 
         let mut elems = Vec::new();
 
+        self.eat_whitespace();
+        if self.peek().tty == TokenType::CloseBracket {
+            self.consume(TokenType::CloseBracket)?;
+            return Ok(JsonData::array(elems));
+        }
+
         loop {
             match self.parse_json() {
                 Ok(elem) => {
@@ -209,6 +715,14 @@ impl <'a> ParsingContext<'a> {
             }
         }))
     }
+    fn parse_null(&mut self) -> Result<JsonData<'a>> {
+        self.eat_whitespace();
+
+        log::debug!("begin parsing null");
+
+        self.consume(TokenType::Null)?;
+        Ok(JsonData::null())
+    }
 
 }
 
@@ -221,6 +735,19 @@ pub fn loads<'a>(source: &'a str) -> Result<JsonData<'a>> {
     Ok(json)
 }
 
+/// Parses any number of json values one after the other, like the output of jq
+pub fn loads_all<'a>(source: &'a str) -> Result<Vec<JsonData<'a>>> {
+    let tokens = tokens::tokenize(source);
+    let mut ctx = ParsingContext::from(source, tokens);
+    let mut values = Vec::new();
+    ctx.eat_whitespace();
+    while ctx.peek().tty != TokenType::Eof {
+        values.push(ctx.parse_json()?);
+        ctx.eat_whitespace();
+    }
+    Ok(values)
+}
+
 
 #[cfg(test)]
 mod tests {
@@ -399,6 +926,31 @@ mod end_user_tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn parse_null() {
+        let source = "null";
+        let expected = JsonData(JsonDataInner {
+            ty: JsonDataType::Null
+        });
+
+        let actual = loads(source).expect("this should parse");
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn parse_empty_containers() {
+        let source = "[ {}, [] ]";
+        let expected = JsonData::array(vec![
+            JsonData::object(vec![]),
+            JsonData::array(vec![]),
+        ]);
+
+        let actual = loads(source).expect("this should parse");
+
+        assert_eq!(actual, expected);
+    }
+
     #[test]
     fn parse_array() {
         let source = "[34, true, \"hello world\"]";
@@ -516,3 +1068,140 @@ mod end_user_tests {
 
 }
 
+
+#[cfg(test)]
+mod dump_tests {
+    use super::*;
+
+    #[test]
+    fn dumps_pretty_like_jq() {
+        let source = "{\"a\": [1, true, null], \"b\": {}, \"c\": []}";
+        let json = loads(source).expect("this should parse");
+
+        let expected = "{\n  \"a\": [\n    1,\n    true,\n    null\n  ],\n  \"b\": {},\n  \"c\": []\n}";
+        assert_eq!(dumps(&json, DumpOptions::default()), expected);
+    }
+
+    #[test]
+    fn dumps_compact_sorted() {
+        let source = "{\"b\": [1, 2], \"a\": \"x\"}";
+        let json = loads(source).expect("this should parse");
+
+        let options = DumpOptions { compact: true, sort_keys: true };
+        assert_eq!(dumps(&json, options), "{\"a\":\"x\",\"b\":[1,2]}");
+    }
+
+    #[test]
+    fn unescape_escapes() {
+        assert_eq!(unescape("\"plain\""), "plain");
+        assert_eq!(unescape("\"a\\\"b\\\\c\\nd\""), "a\"b\\c\nd");
+        assert_eq!(unescape("\"\\u00e9\""), "\u{e9}");
+        assert_eq!(unescape("\"\\ud83d\\ude00\""), "\u{1F600}");
+    }
+
+    #[test]
+    fn quote_round_trips() {
+        let s = "tab\t \"quote\" back\\slash \u{1} \u{e9}";
+        assert_eq!(unescape(&quote(s)), s);
+        assert_eq!(quote("\u{1}\u{7f}"), "\"\\u0001\\u007f\"");
+    }
+
+    #[test]
+    fn loads_all_values() {
+        let values = loads_all("{\"a\": 1}\n2\n\"x\"\n").expect("these should parse");
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[1].as_f64(), Some(2.0));
+        assert_eq!(loads_all(" \n").expect("nothing parses").len(), 0);
+        assert!(loads_all("1 ]").is_err());
+    }
+
+    #[test]
+    fn format_number_whole() {
+        assert_eq!(format_number(3.0), "3");
+        assert_eq!(format_number(-0.5), "-0.5");
+    }
+
+    #[test]
+    fn format_number_like_jq() {
+        assert_eq!(format_number(1e300), "1e+300");
+        assert_eq!(format_number(1e17), "1e+17");
+        assert_eq!(format_number(12345678901234567890123.0), "12345678901234568000000");
+        assert_eq!(format_number(0.001), "0.001");
+        assert_eq!(format_number(0.00001), "1e-05");
+        assert_eq!(format_number(1.5e-7), "1.5e-07");
+        assert_eq!(format_number(-0.0), "-0");
+        assert_eq!(format_number(f64::INFINITY), "1.7976931348623157e+308");
+    }
+
+    #[test]
+    fn dumps_normalizes_numbers() {
+        let json = loads("[1.0, 1e2, 1E+2, 1e300, 0.10]").expect("this should parse");
+        let compact = DumpOptions { compact: true, sort_keys: false };
+        assert_eq!(dumps(&json, compact), "[1,100,100,1e+300,0.1]");
+    }
+
+    #[test]
+    fn repeated_keys_keep_the_last_value() {
+        let json = loads(r#"{"a": 1, "b": 2, "a": 3}"#).expect("this should parse");
+        let compact = DumpOptions { compact: true, sort_keys: false };
+        assert_eq!(dumps(&json, compact), r#"{"a":3,"b":2}"#);
+    }
+
+    fn changes(old: &'static str, new: &'static str, match_key: Option<&str>) -> Vec<String> {
+        let old = loads(old).expect("this should parse");
+        let new = loads(new).expect("this should parse");
+        diff(&old, &new, match_key).iter().map(|change| change.to_string()).collect()
+    }
+
+    #[test]
+    fn paths_quote_odd_keys() {
+        let path = vec![
+            PathSegment::Key("Items".to_string()),
+            PathSegment::Index(4),
+            PathSegment::Key("a b".to_string()),
+            PathSegment::Key("_x1".to_string()),
+        ];
+        assert_eq!(path_to_string(&path), ".Items[4].\"a b\"._x1");
+        assert_eq!(path_to_string(&[PathSegment::Index(0)]), ".[0]");
+        assert_eq!(path_to_string(&[PathSegment::Key("1st".to_string())]), ".\"1st\"");
+        assert_eq!(path_to_string(&[]), ".");
+    }
+
+    #[test]
+    fn diff_objects_by_key() {
+        let old = r#"{"a": 1, "b": {"c": "x", "d": 1.0}, "gone": null}"#;
+        let new = r#"{"b": {"d": 1, "c": "y"}, "a": 1, "new": [true]}"#;
+        assert_eq!(changes(old, new, None), vec![
+            "~ .b.c: \"x\" -> \"y\"",
+            "- .gone: null",
+            "+ .new: [true]",
+        ]);
+    }
+
+    #[test]
+    fn diff_arrays_by_index() {
+        assert_eq!(changes("[1, 2, 3]", "[1, 5]", None), vec![
+            "~ .[1]: 2 -> 5",
+            "- .[2]: 3",
+        ]);
+        assert_eq!(changes("[1]", "{\"a\": 1}", None), vec![
+            "~ .: [1] -> {\"a\":1}",
+        ]);
+    }
+
+    #[test]
+    fn diff_arrays_by_match_key() {
+        let old = r#"[{"id": 1, "v": "a"}, {"id": 2, "v": "b"}, {"id": 3, "v": "c"}]"#;
+        let new = r#"[{"id": 2, "v": "b"}, {"id": 3, "v": "C"}, {"id": 4, "v": "d"}]"#;
+        assert_eq!(changes(old, new, Some("id")), vec![
+            "- .[0]: {\"id\":1,\"v\":\"a\"}",
+            "~ .[1].v: \"c\" -> \"C\"",
+            "+ .[2]: {\"id\":4,\"v\":\"d\"}",
+        ]);
+
+        // without the key on every element, fall back to the index
+        assert_eq!(changes(r#"[{"id": 1}, 2]"#, r#"[{"id": 1}, 3]"#, Some("id")), vec![
+            "~ .[1]: 2 -> 3",
+        ]);
+    }
+}
@@ -1,25 +1,40 @@
 mod json;
 mod cli;
+mod backend;
 mod jq;
+mod eval;
 mod ui;
 mod app;
 mod input;
 mod my_line_editor;
 mod scroll_text;
+mod pipeline;
 mod tokens;
+mod history;
+mod saved;
+mod complete;
+mod builtins;
+mod highlight;
+mod shell;
+mod diff;
+mod inplace;
+mod tree;
 
 use std::{
-    fs::{self, File},
+    fs::{self, File, OpenOptions},
     io::{
         self,
         Read,
+        Write,
     },
-    panic
+    panic,
+    path::Path
 };
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
 
+use cli::Emit;
 use directories::ProjectDirs;
 use ratatui::{
     crossterm::{
@@ -31,12 +46,9 @@ use ratatui::{
     prelude::*,
 };
 
-// formats a number as a human readable size
-fn format_size(size: usize) -> String {
-    humansize::format_size(size, humansize::DECIMAL)
-}
-
 const LOG_FOLDER_NAME: &str = "logs";
+/// Where the ui is drawn
+const TTY_PATH: &str = "/dev/tty";
 /// How many old runs to keep in the log folder
 const MAX_LOG_RUNS_SAVED: usize = 20;
 
@@ -77,7 +89,7 @@ fn configure_logging(cli: &cli::Cli, project_dirs: &ProjectDirs) -> Result<Strin
     for i in to_be_deleted {
         let path = read_dir[i].path();
         fs::remove_file(&path)
-            .with_context(|| format!("attempting to remove old log file"))?;
+            .with_context(|| "attempting to remove old log file".to_string())?;
     }
 
     let log_file = fern::log_file(filepath)
@@ -104,7 +116,7 @@ fn configure_logging(cli: &cli::Cli, project_dirs: &ProjectDirs) -> Result<Strin
 fn read_source(cli: &cli::Cli) -> Result<String> {
     let mut buf = String::new();
 
-    match &cli.input_filename {
+    match cli.input_path() {
         Some(filepath) => {
             // user has supplied a filepath to read from
             log::info!("reading input from {}", filepath.display());
@@ -122,8 +134,36 @@ fn read_source(cli: &cli::Cli) -> Result<String> {
     Ok(buf)
 }
 
-// 16 kb = 16000 bytes
-pub const MAX_STRING_SIZE_TO_PRINT: usize = 16_000;
+/// The query to start with, from --saved, --query or --from-file
+fn initial_query(cli: &cli::Cli, saved: &saved::SavedQueries) -> Result<Option<String>> {
+    if let Some(name) = &cli.saved {
+        let Some(saved) = saved.get(name) else {
+            let names = saved.queries.iter()
+                .map(|saved| saved.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!("there is no saved query named {name:?} (saved queries: {names})");
+        };
+        return Ok(Some(saved.query.clone()));
+    }
+
+    if let Some(query) = &cli.query {
+        return Ok(Some(query.clone()));
+    }
+
+    if let Some(path) = &cli.from_file {
+        if !path.exists() {
+            // a new filter file, it will be created when the query is written out
+            log::info!("query file {} does not exist yet", path.display());
+            return Ok(None);
+        }
+        let query = fs::read_to_string(path)
+            .with_context(|| format!("reading query from {}", path.display()))?;
+        return Ok(Some(query.trim_end_matches('\n').to_string()));
+    }
+
+    Ok(None)
+}
 
 fn main() -> Result<()> {
     let cli = cli::Cli::parse();
@@ -132,43 +172,111 @@ fn main() -> Result<()> {
 
     let log_file = configure_logging(&cli, &project_dirs)?;
 
+    if let Some(cli::Command::Diff { old, new }) = &cli.command {
+        return diff_files(old, new, cli.match_key.as_deref());
+    }
+
     let source = read_source(&cli)?;
 
     // since it's just going to be around for the entire life of the program,
     // just leak the string now and let the OS deal with it
     let source = source.leak();
 
-    let mut app = crate::app::App::init(&cli, source);
+    let history = history::History::load(project_dirs.data_dir())
+        .unwrap_or_else(|e| {
+            log::error!("could not load the query history, starting a new one: {e:?}");
+            history::History::empty(project_dirs.data_dir())
+        });
+
+    let config = backend::BackendConfig::load(project_dirs.config_dir())
+        .unwrap_or_else(|e| {
+            log::error!("could not load the config, using the defaults: {e:?}");
+            backend::BackendConfig::default()
+        });
+
+    // a broken library only matters if we were asked to start from it
+    let saved = match saved::SavedQueries::load(project_dirs.config_dir()) {
+        Ok(saved) => saved,
+        Err(e) if cli.saved.is_some() => return Err(e),
+        Err(e) => {
+            log::error!("could not load the saved queries, starting without them: {e:?}");
+            saved::SavedQueries::unreadable(project_dirs.config_dir(), &e)
+        }
+    };
+    let initial_query = initial_query(&cli, &saved)?;
+
+    let mut app = crate::app::App::init(&cli, &config, source, history, saved);
+    if let Some(query) = initial_query {
+        app.set_query(&query);
+    }
 
     // submit the query once to jq; this will provide the formatting and colorization
     app.submit_query();
     
-    // for testing purposes, if we self parse the json, do so now
+    // for testing purposes, if we self parse the json, do so now. stdout is kept for what we emit
     if cli.self_parse_json {
         let json_data = json::loads(source);
-        println!("{json_data:?}");
+        log::info!("self parsed the input: {json_data:?}");
     }
 
     run(&cli, &mut app)
         .expect("running app");
 
     if cli.print_log_file_path {
-        println!("LOG_FILE: {}", log_file);
+        eprintln!("LOG_FILE: {}", log_file);
     }
 
-    if app.filtered_content().len() < MAX_STRING_SIZE_TO_PRINT {
-        println!("=============================================");
-        println!("{}", app.filtered_content());
-        println!("=============================================");
-    } else {
-        println!("Filtered content is too big to print: {}. Max size is {}.",
-                 format_size(app.filtered_content().len()),
-                 format_size(MAX_STRING_SIZE_TO_PRINT)
-        );
+    if app.aborted {
+        // like fzf, which exits with 130 when it is interrupted
+        std::process::exit(130);
     }
 
-    println!("QUERY: {}", app.query_content());
+    app.finish();
+
+    emit(&app)
+}
+
+/// The `diff` subcommand: prints the changes between two json files, one per line.
+/// Exits with 1 if there are any, like diff(1)
+fn diff_files(old: &Path, new: &Path, match_key: Option<&str>) -> Result<()> {
+    let old_source = fs::read_to_string(old)
+        .with_context(|| format!("reading {}", old.display()))?;
+    let new_source = fs::read_to_string(new)
+        .with_context(|| format!("reading {}", new.display()))?;
+    let old_json = json::loads(&old_source)
+        .with_context(|| format!("parsing {}", old.display()))?;
+    let new_json = json::loads(&new_source)
+        .with_context(|| format!("parsing {}", new.display()))?;
+
+    let changes = json::diff(&old_json, &new_json, match_key);
+    let mut stdout = io::stdout().lock();
+    for change in &changes {
+        writeln!(stdout, "{change}")?;
+    }
+    stdout.flush()?;
 
+    if !changes.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Writes what --emit asks for to stdout, which is kept clean of everything else
+/// so that we can sit in the middle of a pipeline. Fails rather than emit a result that is not
+/// the whole output of the query on screen
+fn emit(app: &app::App) -> Result<()> {
+    if app.emit == Emit::Result && !app.result_is_current() {
+        bail!("not writing out the result, the output shown is not all of the output of the query (it failed, is unfinished or was edited since)");
+    }
+
+    let mut stdout = io::stdout().lock();
+    match app.emit {
+        Emit::Result => stdout.write_all(app.filtered_content().as_bytes())?,
+        Emit::Query => writeln!(stdout, "{}", app.query_content())?,
+        Emit::Command => writeln!(stdout, "{}", app.shell_command())?,
+        Emit::None => {}
+    }
+    stdout.flush()?;
     Ok(())
 }
 
@@ -177,8 +285,11 @@ fn run(cli: &cli::Cli, app: &mut app::App) -> Result<()> {
     log::info!("enabling raw terminal mode");
     enable_raw_mode()?;
 
+    // draw on the terminal itself, stdout is saved for what we emit at the end
+    let mut tty = open_tty()?;
+
     log::info!("entering alternate screen");
-    io::stdout().execute(EnterAlternateScreen)?;
+    tty.execute(EnterAlternateScreen)?;
 
     // Restore the terminal on program failure
     let default_hook = panic::take_hook();
@@ -192,7 +303,7 @@ fn run(cli: &cli::Cli, app: &mut app::App) -> Result<()> {
         default_hook(info);
     }));
 
-    let backend = CrosstermBackend::new(io::stdout());
+    let backend = CrosstermBackend::new(tty);
     let mut term = Terminal::new(backend)?;
 
     log::info!("entering app loop");
@@ -216,10 +327,19 @@ fn run(cli: &cli::Cli, app: &mut app::App) -> Result<()> {
     Ok(())
 }
 
+/// The controlling terminal, even when stdin and stdout are pipes
+fn open_tty() -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(TTY_PATH)
+        .with_context(|| format!("opening the terminal at {TTY_PATH}"))
+}
+
 fn cleanup() -> Result<()> {
     log::info!("cleaning up");
     disable_raw_mode()?;
-    io::stdout().execute(LeaveAlternateScreen)?;
+    open_tty()?.execute(LeaveAlternateScreen)?;
     Ok(())
 }
 
@@ -0,0 +1,220 @@
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// A stage ending in a variable binding, like `. as $x` or `.[] as [$a, $b]`, or in a `label $x`.
+/// It only makes sense together with the stage after it.
+static ENDS_IN_BINDING: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"(?:\bas\s*(\$[a-zA-Z_][a-zA-Z0-9_]*|\[.*\]|\{.*\})|\blabel\s*\$[a-zA-Z_][a-zA-Z0-9_]*)\s*$").expect("compile regex")
+});
+
+/// The keywords that open a block closed by `end`
+const BLOCK_OPENERS: &[&str] = &["if"];
+/// The keyword for a function definition, closed by `;`
+const DEF: &str = "def";
+
+/// Splits a query at its top level pipes, e.g. `.a | map(.b | .c) | length` into
+/// `.a`, `map(.b | .c)` and `length`. Pipes inside of brackets, strings, comments,
+/// `if ... end` blocks and function definitions are left alone, as is `|=`.
+pub fn split_stages(query: &str) -> Vec<&str> {
+    let chars: Vec<(usize, char)> = query.char_indices().collect();
+
+    let mut stages = Vec::new();
+    let mut stage_start = 0;
+    // open brackets and string interpolations
+    let mut depth = 0usize;
+    // open `if` blocks
+    let mut blocks = 0usize;
+    // open `def`s, waiting for their `;`
+    let mut defs = 0usize;
+    // for each open string, the bracket depth of its interpolation (if we are inside one)
+    let mut strings: Vec<usize> = Vec::new();
+    let mut in_string = false;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (at, ch) = chars[i];
+        let next = chars.get(i + 1).map(|&(_, ch)| ch);
+
+        if in_string {
+            match ch {
+                '\\' if next == Some('(') => {
+                    // the interpolation is code again, until its closing paren
+                    strings.push(depth);
+                    depth += 1;
+                    in_string = false;
+                    i += 2;
+                    continue;
+                }
+                '\\' => {
+                    i += 2;
+                    continue;
+                }
+                '"' => in_string = false,
+                _ => {}
+            }
+            i += 1;
+            continue;
+        }
+
+        match ch {
+            '"' => in_string = true,
+            '#' => {
+                // a comment, skip to the end of the line
+                while i < chars.len() && chars[i].1 != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => {
+                depth = depth.saturating_sub(1);
+                // closing an interpolation puts us back in its string
+                if strings.last() == Some(&depth) && ch == ')' {
+                    strings.pop();
+                    in_string = true;
+                }
+            }
+            ';' if depth == 0 => defs = defs.saturating_sub(1),
+            ch if ch.is_ascii_alphabetic() || ch == '_' => {
+                // read the whole word, so that `.end` or `$if` are not keywords
+                let prev = i.checked_sub(1).map(|p| chars[p].1);
+                let start = i;
+                while i < chars.len() && (chars[i].1.is_ascii_alphanumeric() || chars[i].1 == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().map(|&(_, ch)| ch).collect();
+                let is_keyword = !matches!(prev, Some('.') | Some('$'));
+                if is_keyword && depth == 0 {
+                    if BLOCK_OPENERS.contains(&word.as_str()) {
+                        blocks += 1;
+                    } else if word == "end" {
+                        blocks = blocks.saturating_sub(1);
+                    } else if word == DEF {
+                        defs += 1;
+                    }
+                }
+                continue;
+            }
+            '|' if next == Some('=') => {
+                i += 2;
+                continue;
+            }
+            '|' if depth == 0 && blocks == 0 && defs == 0 => {
+                stages.push(&query[stage_start..at]);
+                stage_start = at + 1;
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    stages.push(&query[stage_start..]);
+
+    // glue variable bindings back onto the stage that uses them
+    let mut merged: Vec<&str> = Vec::new();
+    let mut pending: Option<usize> = None;
+    for stage in stages {
+        let start = pending.take().unwrap_or(offset_in(query, stage));
+        let end = offset_in(query, stage) + stage.len();
+        if ENDS_IN_BINDING.is_match(stage) {
+            pending = Some(start);
+            continue;
+        }
+        merged.push(&query[start..end]);
+    }
+    if let Some(start) = pending {
+        merged.push(&query[start..]);
+    }
+
+    merged.into_iter()
+        .filter(|stage| !stage.trim().is_empty())
+        .collect()
+}
+
+/// Where `part` (which must be a slice of `whole`) starts in `whole`
+fn offset_in(whole: &str, part: &str) -> usize {
+    part.as_ptr() as usize - whole.as_ptr() as usize
+}
+
+/// The queries that produce the output of each stage: the first stage, the first two, and so on
+pub fn stage_prefixes(query: &str) -> Vec<String> {
+    split_stages(query)
+        .into_iter()
+        .map(|stage| {
+            let end = offset_in(query, stage) + stage.len();
+            query[..end].trim().to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trimmed(query: &str) -> Vec<&str> {
+        split_stages(query).into_iter().map(str::trim).collect()
+    }
+
+    #[test]
+    fn split_simple_pipeline() {
+        assert_eq!(trimmed(".a | .b|.c"), vec![".a", ".b", ".c"]);
+    }
+
+    #[test]
+    fn split_single_stage() {
+        assert_eq!(trimmed(".a"), vec![".a"]);
+        assert_eq!(trimmed(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn split_respects_brackets() {
+        assert_eq!(trimmed("map(.a | .b) | [.[] | .c] | {x: (.y | .z)}"), vec![
+            "map(.a | .b)",
+            "[.[] | .c]",
+            "{x: (.y | .z)}",
+        ]);
+    }
+
+    #[test]
+    fn split_respects_strings() {
+        assert_eq!(trimmed(r#""a | b" | ."c|d" | "\(.x | .y) | z""#), vec![
+            r#""a | b""#,
+            r#"."c|d""#,
+            r#""\(.x | .y) | z""#,
+        ]);
+        assert_eq!(trimmed(r#""\"|" | ."#), vec![r#""\"|""#, "."]);
+    }
+
+    #[test]
+    fn split_ignores_update_and_comments() {
+        assert_eq!(trimmed(".a |= . + 1 | .b # a | comment\n | .c"), vec![
+            ".a |= . + 1",
+            ".b # a | comment",
+            ".c",
+        ]);
+    }
+
+    #[test]
+    fn split_respects_keywords() {
+        assert_eq!(trimmed("if .a then .b | .c else .d end | .e"), vec![
+            "if .a then .b | .c else .d end",
+            ".e",
+        ]);
+        assert_eq!(trimmed("def f: .a | .b; f | .c"), vec!["def f: .a | .b; f", ".c"]);
+        assert_eq!(trimmed(".end | .if"), vec![".end", ".if"]);
+    }
+
+    #[test]
+    fn split_keeps_bindings_together() {
+        assert_eq!(trimmed(".a as $x | .b | $x"), vec![".a as $x | .b", "$x"]);
+        assert_eq!(trimmed(".[] as [$a, $b] | $a"), vec![".[] as [$a, $b] | $a"]);
+        assert_eq!(trimmed("label $out | .[] | if . > 2 then ., break $out else . end"), vec![
+            "label $out | .[]",
+            "if . > 2 then ., break $out else . end",
+        ]);
+    }
+
+    #[test]
+    fn prefixes() {
+        assert_eq!(stage_prefixes(".a | .b | .c"), vec![".a", ".a | .b", ".a | .b | .c"]);
+    }
+}
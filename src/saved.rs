@@ -0,0 +1,294 @@
+use std::{
+    fs,
+    path::{Path, PathBuf}
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::{
+    history::fuzzy_score,
+    json::{self, DumpOptions, JsonData, JsonDataType, JsonKey}
+};
+
+const SAVED_QUERIES_FILE_NAME: &str = "saved-queries.json";
+
+/// A query the user has kept under a name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SavedQuery {
+    pub name: String,
+    pub description: String,
+    pub query: String,
+}
+
+/// The library of saved queries, kept as a json array of
+/// `{"name": ..., "description": ..., "query": ...}` objects in the config directory
+#[derive(Debug)]
+pub struct SavedQueries {
+    path: PathBuf,
+    pub queries: Vec<SavedQuery>,
+    /// Why the file could not be loaded, if it could not. It is never written over while so,
+    /// to not lose what is in it
+    unreadable: Option<String>,
+}
+
+fn string_field(entries: &[(JsonKey, JsonData)], field: &str) -> Option<String> {
+    entries.iter()
+        .find(|(key, _)| key.name() == field)
+        .and_then(|(_, value)| value.as_str())
+        .map(|s| s.into_owned())
+}
+
+fn parse_saved_queries(source: &str) -> Result<Vec<SavedQuery>> {
+    let json = json::loads(source)?;
+    let JsonDataType::Array { elems } = json.ty() else {
+        bail!("expected an array of saved queries, found {}", json.type_name());
+    };
+    elems.iter()
+        .enumerate()
+        .map(|(i, elem)| {
+            let JsonDataType::Object { entries } = elem.ty() else {
+                bail!("saved query #{i} should be an object, found {}", elem.type_name());
+            };
+            let Some(name) = string_field(entries, "name") else {
+                bail!("saved query #{i} has no name");
+            };
+            let Some(query) = string_field(entries, "query") else {
+                bail!("saved query {name:?} has no query");
+            };
+            let description = string_field(entries, "description").unwrap_or_default();
+            Ok(SavedQuery { name, description, query })
+        })
+        .collect()
+}
+
+fn format_saved_queries(queries: &[SavedQuery]) -> String {
+    let json = JsonData::array(queries.iter()
+        .map(|saved| JsonData::object(vec![
+            (JsonKey::new("name"), JsonData::string(&saved.name)),
+            (JsonKey::new("description"), JsonData::string(&saved.description)),
+            (JsonKey::new("query"), JsonData::string(&saved.query)),
+        ]))
+        .collect());
+    let mut out = json::dumps(&json, DumpOptions::default());
+    out.push('\n');
+    out
+}
+
+impl SavedQueries {
+    /// Reads the saved queries in `config_dir`. A missing file is just an empty library
+    pub fn load(config_dir: &Path) -> Result<SavedQueries> {
+        let path = config_dir.join(SAVED_QUERIES_FILE_NAME);
+        let queries = if path.exists() {
+            let source = fs::read_to_string(&path)
+                .with_context(|| format!("reading saved queries from {}", path.display()))?;
+            parse_saved_queries(&source)
+                .with_context(|| format!("parsing saved queries in {}", path.display()))?
+        } else {
+            Vec::new()
+        };
+        log::info!("loaded {} saved queries from {}", queries.len(), path.display());
+        Ok(SavedQueries { path, queries, unreadable: None })
+    }
+
+    /// An empty library standing in for the one in `config_dir`, which could not be loaded
+    pub fn unreadable(config_dir: &Path, error: &anyhow::Error) -> SavedQueries {
+        SavedQueries {
+            path: config_dir.join(SAVED_QUERIES_FILE_NAME),
+            queries: Vec::new(),
+            unreadable: Some(format!("{error:#}")),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&SavedQuery> {
+        self.queries.iter().find(|saved| saved.name == name)
+    }
+
+    /// Saves the query, replacing any other with the same name, and writes the library out
+    pub fn insert(&mut self, saved: SavedQuery) -> Result<()> {
+        match self.queries.iter_mut().find(|other| other.name == saved.name) {
+            Some(other) => *other = saved,
+            None => self.queries.push(saved),
+        }
+        self.write()
+    }
+
+    /// Forgets the query with this name, and writes the library out
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        self.queries.retain(|saved| saved.name != name);
+        self.write()
+    }
+
+    fn write(&self) -> Result<()> {
+        if let Some(reason) = &self.unreadable {
+            bail!("not writing over {}, which could not be loaded: {reason}", self.path.display());
+        }
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("creating config folder at {}", dir.display()))?;
+        }
+        fs::write(&self.path, format_saved_queries(&self.queries))
+            .with_context(|| format!("writing saved queries to {}", self.path.display()))
+    }
+}
+
+/// Which box of the save form is being typed in
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SaveField {
+    Name,
+    Description,
+}
+
+/// The state of the popup that saves the current query under a name
+#[derive(Debug)]
+pub struct SaveForm {
+    pub name: String,
+    pub description: String,
+    pub field: SaveField,
+}
+
+impl SaveForm {
+    pub fn new() -> SaveForm {
+        SaveForm {
+            name: String::new(),
+            description: String::new(),
+            field: SaveField::Name,
+        }
+    }
+
+    fn current(&mut self) -> &mut String {
+        match self.field {
+            SaveField::Name => &mut self.name,
+            SaveField::Description => &mut self.description,
+        }
+    }
+
+    pub fn push_char(&mut self, ch: char) {
+        self.current().push(ch);
+    }
+
+    pub fn pop_char(&mut self) {
+        self.current().pop();
+    }
+
+    pub fn switch_field(&mut self) {
+        self.field = match self.field {
+            SaveField::Name => SaveField::Description,
+            SaveField::Description => SaveField::Name,
+        };
+    }
+}
+
+/// The state of the popup that loads a saved query
+#[derive(Debug)]
+pub struct SavedPicker {
+    pub needle: String,
+    /// The saved queries that match the needle, best first
+    pub matches: Vec<SavedQuery>,
+    pub selected: usize,
+}
+
+impl SavedPicker {
+    pub fn new(saved: &SavedQueries) -> SavedPicker {
+        let mut picker = SavedPicker {
+            needle: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+        };
+        picker.refilter(saved);
+        picker
+    }
+
+    /// Matches the needle against the names and descriptions
+    pub fn refilter(&mut self, saved: &SavedQueries) {
+        let mut scored: Vec<(i64, &SavedQuery)> = saved.queries
+            .iter()
+            .filter_map(|query| {
+                let haystack = format!("{} {}", query.name, query.description);
+                fuzzy_score(&self.needle, &haystack).map(|score| (score, query))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.name.cmp(&b.1.name)));
+        self.matches = scored.into_iter().map(|(_, query)| query.clone()).collect();
+        self.selected = self.selected.min(self.matches.len().saturating_sub(1));
+    }
+
+    pub fn select_next(&mut self) {
+        if self.selected + 1 < self.matches.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    pub fn selected_query(&self) -> Option<&SavedQuery> {
+        self.matches.get(self.selected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn saved(name: &str, description: &str, query: &str) -> SavedQuery {
+        SavedQuery {
+            name: name.to_string(),
+            description: description.to_string(),
+            query: query.to_string(),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let queries = vec![
+            saved("ids only", "just the \"id\" field", ".[] | .id"),
+            saved("failing baselines", "", ".BaselineIdentities[] | select(.Failing)\n| .Name"),
+        ];
+        let text = format_saved_queries(&queries);
+        assert_eq!(parse_saved_queries(&text).expect("parses"), queries);
+    }
+
+    #[test]
+    fn description_is_optional() {
+        let parsed = parse_saved_queries(r#"[{"name": "a", "query": ".a"}]"#).expect("parses");
+        assert_eq!(parsed, vec![saved("a", "", ".a")]);
+    }
+
+    #[test]
+    fn rejects_missing_fields() {
+        assert!(parse_saved_queries(r#"[{"name": "a"}]"#).is_err());
+        assert!(parse_saved_queries(r#"{"name": "a", "query": ".a"}"#).is_err());
+    }
+
+    #[test]
+    fn unreadable_library_is_not_written_over() {
+        let dir = tempfile::tempdir().expect("create a temp dir");
+        let path = dir.path().join(SAVED_QUERIES_FILE_NAME);
+        fs::write(&path, "[{").expect("write the file");
+
+        let error = SavedQueries::load(dir.path()).expect_err("the file is malformed");
+        let mut library = SavedQueries::unreadable(dir.path(), &error);
+        assert!(library.insert(saved("a", "", ".a")).is_err());
+        assert_eq!(fs::read_to_string(&path).expect("read the file"), "[{");
+    }
+
+    #[test]
+    fn picker_filters_on_name_and_description() {
+        let library = SavedQueries {
+            path: PathBuf::from("/nonexistent/saved-queries.json"),
+            unreadable: None,
+            queries: vec![
+                saved("ids only", "", ".[] | .id"),
+                saved("failing", "baselines that failed", "map(select(.Failing))"),
+            ],
+        };
+        let mut picker = SavedPicker::new(&library);
+        assert_eq!(picker.matches.len(), 2);
+
+        picker.needle.push_str("baseline");
+        picker.refilter(&library);
+        assert_eq!(picker.matches.len(), 1);
+        assert_eq!(picker.selected_query().map(|q| q.name.as_str()), Some("failing"));
+    }
+}
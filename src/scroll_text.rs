@@ -1,14 +1,17 @@
+use std::{cell::Cell, mem, ops::RangeInclusive};
+
 use ratatui::{
     buffer::Buffer,
     layout::Rect,
-    style::Style,
-    text::Line,
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
     widgets::{
         block::BlockExt,
         Block,
         Widget
     }
 };
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 use crate::{
     tokens::{
@@ -22,11 +25,27 @@ use crate::{
 pub struct ScrollText<'a> {
     // the lines to render
     lines: Vec<Line<'a>>,
+    // how many columns the widest line takes up
+    widest: usize,
     // the first line that it will actually render
     line_offset: usize,
-    // the styles to use
+    // the current line, which can be anywhere, even off screen
+    cursor: usize,
+    // where the selection started, if there is one. it runs to the cursor
+    anchor: Option<usize>,
+    // how many lines fit, as of the last render
+    height: Cell<usize>,
+    // how wide the screen is, as of the last render
+    width: Cell<usize>,
+    // how many columns are scrolled off to the left
+    column_offset: usize,
+    // whether long lines carry on over the next rows, rather than being cut off
+    wrap: bool,
 }
 
+/// Goes in front of the rows that carry on a wrapped line
+const CONTINUATION_MARKER: &str = "↳ ";
+
 impl <'a> ScrollText<'a> {
     pub fn from(content: String) -> ScrollText<'a> {
         // TODO: how to avoid all the copying here??
@@ -34,17 +53,37 @@ impl <'a> ScrollText<'a> {
     }
 
     pub fn from_content(content: String) -> ScrollText<'a> {
-        let lines = content.lines()
-            .map(|l| Line::from(l.to_string()))
-            .collect();
-
+        let mut scroll_text = Self::from_lines(Vec::new());
+        scroll_text.append_content(content.as_str());
+        scroll_text
+    }
+    pub fn from_lines(lines: Vec<Line<'a>>) -> ScrollText<'a> {
         Self {
             line_offset: 0,
+            widest: lines.iter().map(Line::width).max().unwrap_or(0),
             lines,
-        } 
+            cursor: 0,
+            anchor: None,
+            height: Cell::new(0),
+            width: Cell::new(0),
+            column_offset: 0,
+            wrap: false,
+        }
     }
     pub fn from_tokens<'b>(tokens: &[Token<'b>]) -> ScrollText<'a> {
-        let mut lines = Vec::new();
+        let mut scroll_text = Self::from_lines(Vec::new());
+        scroll_text.append_tokens(tokens);
+        scroll_text
+    }
+
+    /// Adds more lines at the end. Content is expected to come in whole lines
+    pub fn append_content(&mut self, content: &str) {
+        for line in content.lines() {
+            self.push_line(Line::from(line.to_string()));
+        }
+    }
+    /// Adds more lines at the end. Tokens are expected to come in whole lines
+    pub fn append_tokens<'b>(&mut self, tokens: &[Token<'b>]) {
         let mut curr_line = Vec::new();
         for tok in tokens {
 
@@ -54,15 +93,122 @@ impl <'a> ScrollText<'a> {
 
             if tok.tty == TokenType::Newline {
                 let line = Line::from(curr_line.clone());
-                lines.push(line);
+                self.push_line(line);
                 curr_line.clear();
             }
         }
+        // the last line may not end in a newline
+        if !curr_line.is_empty() {
+            self.push_line(Line::from(curr_line));
+        }
+    }
+    fn push_line(&mut self, line: Line<'a>) {
+        self.widest = self.widest.max(line.width());
+        self.lines.push(line);
+    }
 
-        Self {
-            line_offset: 0,
-            lines
+    /// The line at the top
+    pub fn line_offset(&self) -> usize {
+        self.line_offset
+    }
+
+    /// The current line
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// The lines from where the selection started to the cursor, in order
+    pub fn selection(&self) -> Option<RangeInclusive<usize>> {
+        let anchor = self.anchor?;
+        Some(anchor.min(self.cursor)..=anchor.max(self.cursor))
+    }
+
+    /// Moves the current line, scrolling just enough to keep it on screen
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.lines.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+        self.follow_cursor();
+    }
+    /// Moves the current line by a screenful, up for negative `pages`
+    pub fn move_cursor_pages(&mut self, pages: isize) {
+        let page = self.height.get().max(1) as isize;
+        self.move_cursor(pages * page);
+    }
+    pub fn cursor_to_start(&mut self) {
+        self.cursor = 0;
+        self.follow_cursor();
+    }
+    pub fn cursor_to_end(&mut self) {
+        self.cursor = self.lines.len().saturating_sub(1);
+        self.follow_cursor();
+    }
+
+    /// Starts selecting from the current line, or stops selecting
+    pub fn toggle_selection(&mut self) {
+        self.anchor = match self.anchor {
+            Some(_) => None,
+            None => Some(self.cursor),
+        };
+    }
+    /// Returns false if there was no selection to clear
+    pub fn clear_selection(&mut self) -> bool {
+        self.anchor.take().is_some()
+    }
+
+    /// How many columns are scrolled off to the left
+    pub fn column_offset(&self) -> usize {
+        self.column_offset
+    }
+
+    /// Scrolls sideways, right for positive `delta`, up to the end of the longest line
+    pub fn scroll_columns(&mut self, delta: isize) {
+        self.column_offset = self.column_offset
+            .saturating_add_signed(delta)
+            .min(self.widest.saturating_sub(1));
+    }
+
+    pub fn wrap(&self) -> bool {
+        self.wrap
+    }
+    /// Wrapped lines all start at the first column, so this also scrolls back to it
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+        self.column_offset = 0;
+        self.follow_cursor();
+    }
+
+    fn follow_cursor(&mut self) {
+        let height = self.height.get().max(1);
+        if self.cursor < self.line_offset {
+            self.line_offset = self.cursor;
+            return;
+        }
+        if !self.wrap {
+            self.line_offset = self.line_offset.max((self.cursor + 1).saturating_sub(height));
+            return;
+        }
+        // wrapped lines may take up more than one row each, so walk back from the cursor for as many lines as fit
+        let mut first = self.cursor;
+        let mut rows = self.rows_of(first);
+        while first > self.line_offset {
+            let above = self.rows_of(first - 1);
+            if rows + above > height {
+                break;
+            }
+            rows += above;
+            first -= 1;
         }
+        self.line_offset = first;
+    }
+
+    /// How many rows the line takes up on screen
+    fn rows_of(&self, idx: usize) -> usize {
+        let width = self.width.get();
+        let Some(line) = self.lines.get(idx) else { return 1; };
+        if !self.wrap || line.width() <= width {
+            return 1;
+        }
+        wrap_line(line, width).len()
     }
 
     pub fn scroll_up(&mut self) {
@@ -79,36 +225,102 @@ impl <'a> ScrollText<'a> {
             scroll_text: self,
             style: Style::default(),
             block: None,
+            show_cursor: false,
         }
     }
 
-    fn render_ref(&self, area: Rect, buf: &mut Buffer) {
+    fn render_ref(&self, area: Rect, buf: &mut Buffer, show_cursor: bool) {
         let area = area.intersection(buf.area);
-        
-        // TODO: efficiency
-        for (row_idx, row) in area.rows().enumerate() {
-            let idx = row_idx + self.line_offset;
-            let Some(line) = self.lines.get(idx) else { continue; };
-
-            let x_offset = 0;
-
-            let line_area = Rect {
-                x: area.x + x_offset,
-                y: row.y,
-                width: area.width - x_offset,
-                height: 1
+        self.height.set(area.height as usize);
+        self.width.set(area.width as usize);
+        let selection = self.selection();
+
+        let mut rows = area.rows();
+        for (idx, line) in self.lines.iter().enumerate().skip(self.line_offset) {
+            let pieces = if self.wrap {
+                wrap_line(line, area.width as usize)
+            } else {
+                vec![slice_columns(line, self.column_offset, area.width as usize)]
             };
-            line.render(line_area, buf);
+            for piece in pieces {
+                let Some(line_area) = rows.next() else { return; };
+                piece.render(line_area, buf);
 
+                if selection.as_ref().is_some_and(|selection| selection.contains(&idx)) {
+                    buf.set_style(line_area, Style::default().bg(Color::DarkGray));
+                }
+                if show_cursor && idx == self.cursor {
+                    buf.set_style(line_area, Style::default().add_modifier(Modifier::REVERSED));
+                }
+            }
         }
     }
 }
 
+/// The columns of the line from `start`, `len` of them, keeping their styles.
+/// Wide characters cut in two by either edge are left out
+fn slice_columns<'a>(line: &Line<'a>, start: usize, len: usize) -> Line<'a> {
+    let end = start + len;
+    let mut column = 0;
+    let mut spans = Vec::new();
+    for span in &line.spans {
+        if column >= end {
+            break;
+        }
+        let span_width = span.width();
+        if column + span_width <= start {
+            column += span_width;
+            continue;
+        }
+        let mut content = String::new();
+        for c in span.content.chars() {
+            let width = c.width().unwrap_or(0);
+            if column >= start && column + width <= end {
+                content.push(c);
+            }
+            column += width;
+        }
+        spans.push(Span::styled(content, span.style));
+    }
+    Line::from(spans).style(line.style)
+}
+
+/// The line cut up into rows of `width` columns, with a marker in front of every row after the first
+fn wrap_line<'a>(line: &Line<'a>, width: usize) -> Vec<Line<'a>> {
+    let marker_width = CONTINUATION_MARKER.width();
+    if width <= marker_width || line.width() <= width {
+        return vec![line.clone()];
+    }
+    let mut pieces = Vec::new();
+    let mut spans = Vec::new();
+    let mut row_width = width;
+    let mut room = width;
+    for span in &line.spans {
+        let mut content = String::new();
+        for c in span.content.chars() {
+            let char_width = c.width().unwrap_or(0);
+            // a wide character that does not fit goes on the next row, unless the row is still empty
+            if char_width > room && room < row_width {
+                spans.push(Span::styled(mem::take(&mut content), span.style));
+                pieces.push(Line::from(mem::take(&mut spans)).style(line.style));
+                spans.push(Span::styled(CONTINUATION_MARKER, Style::default().fg(Color::DarkGray)));
+                row_width = width - marker_width;
+                room = row_width;
+            }
+            content.push(c);
+            room = room.saturating_sub(char_width);
+        }
+        spans.push(Span::styled(content, span.style));
+    }
+    pieces.push(Line::from(spans).style(line.style));
+    pieces
+}
+
 pub struct ScrollTextRef<'a, 'b> {
     scroll_text: &'b ScrollText<'a>,
     block: Option<Block<'a>>,
     style: Style,
-    
+    show_cursor: bool,
 }
 impl <'a, 'b> Widget for ScrollTextRef<'a, 'b> {
     fn render(self, area: Rect, buf: &mut Buffer) {
@@ -120,7 +332,7 @@ impl <'a, 'b> Widget for ScrollTextRef<'a, 'b> {
             area = self.block.inner_if_some(area);
         }
 
-        self.scroll_text.render_ref(area, buf);
+        self.scroll_text.render_ref(area, buf, self.show_cursor);
     }
 }
 impl <'a, 'b> ScrollTextRef<'a, 'b> {
@@ -128,4 +340,121 @@ impl <'a, 'b> ScrollTextRef<'a, 'b> {
         self.block = Some(block);
         self
     }
+    /// Highlight the current line
+    pub fn show_cursor(mut self, show_cursor: bool) -> Self {
+        self.show_cursor = show_cursor;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: usize, height: usize) -> ScrollText<'static> {
+        let content: Vec<String> = (0..lines).map(|i| i.to_string()).collect();
+        let scroll_text = ScrollText::from_content(content.join("\n"));
+        scroll_text.height.set(height);
+        scroll_text
+    }
+
+    #[test]
+    fn cursor_moves_without_scrolling_until_it_leaves_the_screen() {
+        let mut scroll_text = text(20, 5);
+        scroll_text.move_cursor(4);
+        assert_eq!((scroll_text.cursor(), scroll_text.line_offset()), (4, 0));
+        scroll_text.move_cursor(1);
+        assert_eq!((scroll_text.cursor(), scroll_text.line_offset()), (5, 1));
+        scroll_text.move_cursor_pages(3);
+        assert_eq!((scroll_text.cursor(), scroll_text.line_offset()), (19, 15));
+        scroll_text.move_cursor(-10);
+        assert_eq!((scroll_text.cursor(), scroll_text.line_offset()), (9, 9));
+
+        // scrolling leaves the cursor where it is
+        scroll_text.scroll_down();
+        assert_eq!((scroll_text.cursor(), scroll_text.line_offset()), (9, 10));
+    }
+
+    fn texts(lines: &[Line]) -> Vec<String> {
+        lines.iter().map(|line| line.spans.iter().map(|span| span.content.as_ref()).collect()).collect()
+    }
+
+    #[test]
+    fn slices_across_spans() {
+        let line = Line::from(vec![Span::raw("abc"), Span::raw("defg")]);
+        assert_eq!(texts(&[slice_columns(&line, 2, 3)]), vec!["cde"]);
+        assert_eq!(texts(&[slice_columns(&line, 5, 10)]), vec!["fg"]);
+        assert_eq!(texts(&[slice_columns(&line, 9, 10)]), vec![""]);
+    }
+
+    #[test]
+    fn slices_by_display_width() {
+        let line = Line::from(vec![Span::raw("a日本"), Span::raw("語b")]);
+        assert_eq!(texts(&[slice_columns(&line, 1, 4)]), vec!["日本"]);
+        // 本 straddles the left edge, 語 the right one
+        assert_eq!(texts(&[slice_columns(&line, 4, 2)]), vec![""]);
+        assert_eq!(texts(&[slice_columns(&line, 5, 3)]), vec!["語b"]);
+    }
+
+    #[test]
+    fn wraps_with_a_marker() {
+        let line = Line::raw("abcdefghij");
+        assert_eq!(texts(&wrap_line(&line, 4)), vec!["abcd", "↳ ef", "↳ gh", "↳ ij"]);
+        assert_eq!(texts(&wrap_line(&line, 10)), vec!["abcdefghij"]);
+    }
+
+    #[test]
+    fn wraps_wide_characters_whole() {
+        let line = Line::from(vec![Span::raw("a日"), Span::raw("本語")]);
+        assert_eq!(texts(&wrap_line(&line, 4)), vec!["a日", "↳ 本", "↳ 語"]);
+    }
+
+    #[test]
+    fn scrolls_sideways_up_to_the_widest_line() {
+        let mut scroll_text = ScrollText::from_content("ab\n日本".to_string());
+        scroll_text.append_content("abc");
+        scroll_text.scroll_columns(10);
+        assert_eq!(scroll_text.column_offset(), 3);
+    }
+
+    #[test]
+    fn wrapped_lines_push_the_cursor_down_sooner() {
+        let mut scroll_text = ScrollText::from_content("0\n1234567890\n2\n3".to_string());
+        scroll_text.height.set(3);
+        scroll_text.width.set(4);
+        scroll_text.set_wrap(true);
+        // the second line takes up 1 + 3 rows
+        scroll_text.move_cursor(1);
+        assert_eq!(scroll_text.line_offset(), 1);
+        scroll_text.move_cursor(1);
+        assert_eq!(scroll_text.line_offset(), 2);
+    }
+
+    #[test]
+    fn jumps_to_the_end_of_a_large_buffer() {
+        let content: Vec<String> = (0..50_000).map(|i| format!("{i:0>12}")).collect();
+        let mut scroll_text = ScrollText::from_content(content.join("\n"));
+        scroll_text.height.set(10);
+        scroll_text.width.set(8);
+        scroll_text.cursor_to_end();
+        assert_eq!(scroll_text.line_offset(), 49_990);
+
+        // every line takes up two rows, so 5 of them fit
+        scroll_text.cursor_to_start();
+        scroll_text.set_wrap(true);
+        scroll_text.cursor_to_end();
+        assert_eq!((scroll_text.cursor(), scroll_text.line_offset()), (49_999, 49_995));
+    }
+
+    #[test]
+    fn selection_runs_from_the_anchor_to_the_cursor() {
+        let mut scroll_text = text(20, 5);
+        scroll_text.move_cursor(6);
+        scroll_text.toggle_selection();
+        scroll_text.move_cursor(-2);
+        assert_eq!(scroll_text.selection(), Some(4..=6));
+        assert!(scroll_text.clear_selection());
+        assert_eq!(scroll_text.selection(), None);
+        assert!(!scroll_text.clear_selection());
+    }
 }
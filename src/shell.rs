@@ -0,0 +1,51 @@
+use std::borrow::Cow;
+
+/// Chars that never need quoting in a POSIX shell
+fn is_safe(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || "@%+=:,./-_".contains(ch)
+}
+
+/// Quotes an argument for a POSIX shell, so it is passed along exactly as it is.
+/// Single quotes keep everything literal, except a single quote itself, which becomes `'\''`
+pub fn quote(arg: &str) -> Cow<'_, str> {
+    if !arg.is_empty() && arg.chars().all(is_safe) {
+        return Cow::Borrowed(arg);
+    }
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+/// A command line that runs `argv` when pasted into a shell
+pub fn command_line<S: AsRef<str>>(argv: &[S]) -> String {
+    argv.iter()
+        .map(|arg| quote(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn safe_args_are_left_alone() {
+        assert_eq!(quote("jq"), "jq");
+        assert_eq!(quote("-r"), "-r");
+        assert_eq!(quote("./input.json"), "./input.json");
+        assert_eq!(quote("."), ".");
+    }
+
+    #[test]
+    fn other_args_are_quoted() {
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote(".items[] | .a"), "'.items[] | .a'");
+        assert_eq!(quote("$x"), "'$x'");
+        assert_eq!(quote("it's"), r"'it'\''s'");
+        assert_eq!(quote(".a\n| .b"), "'.a\n| .b'");
+    }
+
+    #[test]
+    fn whole_command() {
+        let argv = ["jq", "-r", "--arg", "x", "1", ".items[] | select(.a == $x)", "input.json"];
+        assert_eq!(command_line(&argv), "jq -r --arg x 1 '.items[] | select(.a == $x)' input.json");
+    }
+}
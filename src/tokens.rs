@@ -13,11 +13,12 @@ static PATTERN_TOKENS: Lazy<Vec<(TokenType, Regex)>> = Lazy::new(|| {
         (TokenType::Whitespace, Regex::new(r"^[ \t]+").expect("compile regex")),
         (TokenType::Boolean, Regex::new(r"^true").expect("compile regex")),
         (TokenType::Boolean, Regex::new(r"^false").expect("compile regex")),
+        (TokenType::Null, Regex::new(r"^null").expect("compile regex")),
         (TokenType::String, Regex::new(r#"^"(?:[^"\\]|\\.)*""#).expect("compile regex")),
-        // Splitting up the numbers into 3 patterns for clarity
-        (TokenType::Number, Regex::new(r"^-?\d*\.\d+").expect("compile regex")),
-        (TokenType::Number, Regex::new(r"^-?\d+\.\d*").expect("compile regex")),
-        (TokenType::Number, Regex::new(r"^-?\d+").expect("compile regex")),
+        // Splitting up the numbers into 3 patterns for clarity, each with an optional exponent like jq's `1e+300`
+        (TokenType::Number, Regex::new(r"^-?\d*\.\d+(?:[eE][+-]?\d+)?").expect("compile regex")),
+        (TokenType::Number, Regex::new(r"^-?\d+\.\d*(?:[eE][+-]?\d+)?").expect("compile regex")),
+        (TokenType::Number, Regex::new(r"^-?\d+(?:[eE][+-]?\d+)?").expect("compile regex")),
     ]
 });
 
@@ -35,6 +36,7 @@ pub enum TokenType {
     String,
     Number,
     Boolean,
+    Null,
     InvalidChar,
     Eof,
 }
@@ -59,17 +61,14 @@ impl <'a> TokenizeContext<'a> {
 
 impl TokenType {
     pub fn is_whitespace(self) -> bool {
-        match self {
-            TokenType::Whitespace | TokenType::Newline => true,
-            _ => false,
-        }
+        matches!(self, TokenType::Whitespace | TokenType::Newline)
 
     }
 }
 
-pub fn tokenize(source: &str) -> Vec<Token> {
+pub fn tokenize(source: &str) -> Vec<Token<'_>> {
     let mut ctx = TokenizeContext::from(source);
-    while ctx.source.len() > 0 {
+    while !ctx.source.is_empty() {
 
         let tok = PATTERN_TOKENS
             .iter()
@@ -77,7 +76,7 @@ pub fn tokenize(source: &str) -> Vec<Token> {
                 log::debug!("running {re:?}.find({:?})", ctx.source);
                 re.find(ctx.source)
                     .map(|capt| Token {
-                        tty: tty.clone(),
+                        tty: *tty,
                         lex: capt.as_str()
                     })
             })
@@ -226,6 +225,17 @@ mod tests {
         })
     }
 
+    #[test]
+    fn tokenize_number_exponent() {
+        for source in ["1e+300", "1.5e-07", "2E3"] {
+            let tokens = tokenize(source);
+            assert_eq!(tokens, vec![Token {
+                tty: TokenType::Number,
+                lex: source
+            }]);
+        }
+    }
+
     #[test]
     fn tokenize_string() {
         let source = "\"hello world 123 - + []\"";
@@ -296,6 +306,20 @@ mod tests {
         })
     }
 
+    #[test]
+    fn tokenize_null() {
+        let source = "null";
+        let tokens = tokenize(source);
+
+        assert_eq!(tokens.len(), 1,
+            "number of tokens did not match, tokens = {:?}", tokens
+        );
+        assert_eq!(&tokens[0], &Token {
+            tty: TokenType::Null,
+            lex: "null"
+        })
+    }
+
     #[test]
     fn tokenize_boolean_false2() {
         let source = "\"hello world\"false";
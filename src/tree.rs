@@ -0,0 +1,404 @@
+//! The output as a tree of json values, whose objects and arrays can be folded away.
+
+use std::cell::Cell;
+
+use anyhow::Result;
+
+use crate::{
+    json::{self, DumpOptions, JsonData, JsonDataType, PathSegment},
+    tokens::TokenType
+};
+
+/// What a node of the tree holds
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NodeValue {
+    Object { len: usize },
+    Array { len: usize },
+    /// A string, number, boolean or null, written out the way jq writes it
+    Scalar { ty: TokenType, text: String },
+}
+
+#[derive(Debug)]
+pub struct TreeNode {
+    pub depth: usize,
+    /// How the parent gets to this node. `None` for the values at the top
+    pub segment: Option<PathSegment>,
+    pub value: NodeValue,
+    pub parent: Option<usize>,
+    /// One past the last node below this one
+    pub end: usize,
+    /// Whether a comma follows it
+    pub comma: bool,
+    pub collapsed: bool,
+    /// The line jq prints it on (when pretty printing), which is its row with nothing folded
+    pub line: usize,
+    /// The line of its closing bracket, or `line` if it has none of its own
+    pub last_line: usize,
+}
+
+impl TreeNode {
+    /// Whether there is anything inside of it to fold away
+    pub fn can_fold(&self) -> bool {
+        matches!(self.value, NodeValue::Object { len } | NodeValue::Array { len } if len > 0)
+    }
+    /// Whether its children are showing
+    pub fn is_open(&self) -> bool {
+        self.can_fold() && !self.collapsed
+    }
+}
+
+/// One row on screen: the start of a node, or the closing bracket of an unfolded object or array
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub node: usize,
+    pub closing: bool,
+}
+
+#[derive(Debug)]
+pub struct JsonTree {
+    /// Every value, parents before their children
+    nodes: Vec<TreeNode>,
+    /// The rows that are not folded away
+    rows: Vec<Row>,
+    /// Index into `rows`
+    cursor: usize,
+    /// The first row on screen. It follows the cursor as it is drawn, so it is kept in a cell
+    offset: Cell<usize>,
+    /// How many rows fit, as of the last draw
+    height: Cell<usize>,
+}
+
+impl JsonTree {
+    /// The tree of the output of a query, which may be any number of json values
+    pub fn parse(source: &str) -> Result<JsonTree> {
+        let values = json::loads_all(source)?;
+        Ok(JsonTree::from_values(&values))
+    }
+
+    pub fn from_values(values: &[JsonData]) -> JsonTree {
+        let mut nodes = Vec::new();
+        let mut line = 0;
+        for value in values {
+            push_node(&mut nodes, value, 0, None, None, false, &mut line);
+        }
+        let mut tree = JsonTree {
+            nodes,
+            rows: Vec::new(),
+            cursor: 0,
+            offset: Cell::new(0),
+            height: Cell::new(0),
+        };
+        tree.refresh_rows();
+        tree
+    }
+
+    pub fn node(&self, index: usize) -> &TreeNode {
+        &self.nodes[index]
+    }
+    pub fn rows(&self) -> &[Row] {
+        &self.rows
+    }
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+    /// The node whose row the cursor is on
+    pub fn cursor_node(&self) -> Option<usize> {
+        self.rows.get(self.cursor).map(|row| row.node)
+    }
+
+    /// The node printed on the line, or whose closing bracket is on it
+    pub fn node_at_line(&self, line: usize) -> Option<usize> {
+        // the last node to start at or before the line, then out to the one that spans it
+        let mut at = self.nodes.partition_point(|node| node.line <= line).checked_sub(1);
+        while let Some(i) = at {
+            if self.nodes[i].last_line >= line {
+                return Some(i);
+            }
+            at = self.nodes[i].parent;
+        }
+        None
+    }
+
+    /// How many values there are at the top, one per value the query output
+    pub fn value_count(&self) -> usize {
+        let mut count = 0;
+        let mut at = 0;
+        while let Some(node) = self.nodes.get(at) {
+            count += 1;
+            at = node.end;
+        }
+        count
+    }
+
+    /// The path from the top of the value the node is in, down to the node
+    pub fn path(&self, index: usize) -> Vec<PathSegment> {
+        let mut path = Vec::new();
+        let mut at = Some(index);
+        while let Some(i) = at {
+            if let Some(segment) = &self.nodes[i].segment {
+                path.push(segment.clone());
+            }
+            at = self.nodes[i].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    pub fn move_cursor(&mut self, delta: isize) {
+        let last = self.rows.len().saturating_sub(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).min(last);
+    }
+    /// Moves the cursor by a screenful, up for negative `pages`
+    pub fn move_cursor_pages(&mut self, pages: isize) {
+        let page = self.height.get().max(1) as isize;
+        self.move_cursor(pages * page);
+    }
+    pub fn cursor_to_start(&mut self) {
+        self.cursor = 0;
+    }
+    pub fn cursor_to_end(&mut self) {
+        self.cursor = self.rows.len().saturating_sub(1);
+    }
+
+    /// Folds or unfolds the object or array under the cursor
+    pub fn toggle(&mut self) {
+        let Some(index) = self.cursor_node() else { return; };
+        if self.nodes[index].can_fold() {
+            self.nodes[index].collapsed = !self.nodes[index].collapsed;
+            self.refresh_rows_at(index);
+        }
+    }
+
+    /// Folds the object or array under the cursor. If it is folded already, moves up to its parent
+    pub fn collapse(&mut self) {
+        let Some(index) = self.cursor_node() else { return; };
+        if self.nodes[index].is_open() {
+            self.nodes[index].collapsed = true;
+            self.refresh_rows_at(index);
+        } else if let Some(parent) = self.nodes[index].parent {
+            self.cursor_to_node(parent);
+        }
+    }
+
+    /// Unfolds the object or array under the cursor
+    pub fn expand(&mut self) {
+        let Some(index) = self.cursor_node() else { return; };
+        if self.nodes[index].can_fold() && self.nodes[index].collapsed {
+            self.nodes[index].collapsed = false;
+            self.refresh_rows_at(index);
+        }
+    }
+
+    pub fn expand_all(&mut self) {
+        let at = self.cursor_node();
+        for node in &mut self.nodes {
+            node.collapsed = false;
+        }
+        self.refresh_rows();
+        if let Some(at) = at {
+            self.cursor_to_node(at);
+        }
+    }
+
+    /// Folds everything `depth` levels down, and unfolds everything above that.
+    /// At depth 0 only the values at the top show
+    pub fn collapse_to_depth(&mut self, depth: usize) {
+        let at = self.cursor_node();
+        for node in &mut self.nodes {
+            node.collapsed = node.depth >= depth;
+        }
+        self.refresh_rows();
+        if let Some(at) = at {
+            self.cursor_to_node(at);
+        }
+    }
+
+    /// The rows that fit in `height`, scrolled so that the cursor is among them
+    pub fn visible_rows(&self, height: usize) -> std::ops::Range<usize> {
+        self.height.set(height);
+        let mut offset = self.offset.get();
+        if self.cursor < offset {
+            offset = self.cursor;
+        } else if height > 0 && self.cursor >= offset + height {
+            offset = self.cursor + 1 - height;
+        }
+        self.offset.set(offset);
+        offset..(offset + height).min(self.rows.len())
+    }
+
+    /// Puts the cursor on the row of the node, or of the closest parent that is showing
+    fn cursor_to_node(&mut self, index: usize) {
+        let mut at = Some(index);
+        while let Some(i) = at {
+            if let Some(row) = self.rows.iter().position(|row| row.node == i && !row.closing) {
+                self.cursor = row;
+                return;
+            }
+            at = self.nodes[i].parent;
+        }
+        self.cursor = 0;
+    }
+
+    fn refresh_rows_at(&mut self, index: usize) {
+        self.refresh_rows();
+        self.cursor_to_node(index);
+    }
+
+    fn refresh_rows(&mut self) {
+        let mut rows = Vec::new();
+        let mut i = 0;
+        while i < self.nodes.len() {
+            self.push_rows(i, &mut rows);
+            i = self.nodes[i].end;
+        }
+        self.rows = rows;
+        self.cursor = self.cursor.min(self.rows.len().saturating_sub(1));
+    }
+
+    fn push_rows(&self, index: usize, rows: &mut Vec<Row>) {
+        rows.push(Row { node: index, closing: false });
+        let node = &self.nodes[index];
+        if !node.is_open() {
+            return;
+        }
+        let mut child = index + 1;
+        while child < node.end {
+            self.push_rows(child, rows);
+            child = self.nodes[child].end;
+        }
+        rows.push(Row { node: index, closing: true });
+    }
+}
+
+fn push_node(
+    nodes: &mut Vec<TreeNode>,
+    value: &JsonData,
+    depth: usize,
+    segment: Option<PathSegment>,
+    parent: Option<usize>,
+    comma: bool,
+    line: &mut usize,
+) {
+    let index = nodes.len();
+    let node_value = match value.ty() {
+        JsonDataType::Object { entries } => NodeValue::Object { len: entries.len() },
+        JsonDataType::Array { elems } => NodeValue::Array { len: elems.len() },
+        ty => {
+            let ty = match ty {
+                JsonDataType::Str { .. } => TokenType::String,
+                JsonDataType::Number { .. } => TokenType::Number,
+                JsonDataType::Boolean { .. } => TokenType::Boolean,
+                _ => TokenType::Null,
+            };
+            NodeValue::Scalar { ty, text: json::dumps(value, DumpOptions::default()) }
+        }
+    };
+    nodes.push(TreeNode {
+        depth,
+        segment,
+        value: node_value,
+        parent,
+        end: index + 1,
+        comma,
+        collapsed: false,
+        line: *line,
+        last_line: *line,
+    });
+    *line += 1;
+
+    match value.ty() {
+        JsonDataType::Object { entries } => {
+            for (i, (key, child)) in entries.iter().enumerate() {
+                let segment = PathSegment::Key(key.name().into_owned());
+                push_node(nodes, child, depth + 1, Some(segment), Some(index), i + 1 < entries.len(), line);
+            }
+        }
+        JsonDataType::Array { elems } => {
+            for (i, child) in elems.iter().enumerate() {
+                push_node(nodes, child, depth + 1, Some(PathSegment::Index(i)), Some(index), i + 1 < elems.len(), line);
+            }
+        }
+        _ => {}
+    }
+    nodes[index].end = nodes.len();
+    if nodes[index].can_fold() {
+        nodes[index].last_line = *line;
+        *line += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tree(source: &str) -> JsonTree {
+        JsonTree::parse(source).expect("this should parse")
+    }
+
+    /// The rows by the key or index of their node, with a `/` in front for the closing ones
+    fn rows(tree: &JsonTree) -> Vec<String> {
+        tree.rows().iter()
+            .map(|row| {
+                let label = match &tree.node(row.node).segment {
+                    Some(PathSegment::Key(key)) => key.clone(),
+                    Some(PathSegment::Index(index)) => index.to_string(),
+                    None => ".".to_string(),
+                };
+                if row.closing { format!("/{label}") } else { label }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rows_follow_the_layout_of_jq() {
+        let tree = tree(r#"{"a": [1, {}], "b": null} 2"#);
+        assert_eq!(rows(&tree), vec![".", "a", "0", "1", "/a", "b", "/.", "."]);
+        assert!(tree.node(1).comma);
+        assert!(!tree.node(3).comma);
+        assert_eq!(tree.value_count(), 2);
+        assert_eq!(JsonTree::parse("").expect("nothing parses").value_count(), 0);
+    }
+
+    #[test]
+    fn fold_and_unfold() {
+        let mut tree = tree(r#"{"a": [1, 2], "b": {"c": true}}"#);
+        tree.move_cursor(1);
+        tree.toggle();
+        assert_eq!(rows(&tree), vec![".", "a", "b", "c", "/b", "/."]);
+
+        // folding something that is folded goes up to the parent
+        tree.collapse();
+        assert_eq!(tree.cursor(), 0);
+        tree.collapse();
+        assert_eq!(rows(&tree), vec!["."]);
+
+        tree.expand_all();
+        assert_eq!(rows(&tree).len(), 9);
+    }
+
+    #[test]
+    fn collapse_to_depth_keeps_the_cursor_on_a_parent() {
+        let mut tree = tree(r#"{"a": {"b": {"c": 1}}}"#);
+        tree.move_cursor(3);
+        assert_eq!(tree.node(tree.cursor_node().expect("on a row")).segment, Some(PathSegment::Key("c".to_string())));
+
+        tree.collapse_to_depth(1);
+        assert_eq!(rows(&tree), vec![".", "a", "/."]);
+        assert_eq!(tree.cursor(), 1);
+    }
+
+    #[test]
+    fn lines_match_the_layout_of_jq() {
+        let tree = tree(r#"{"a": [1, {}], "b": null} 2"#);
+        let lines: Vec<Option<usize>> = (0..9).map(|line| tree.node_at_line(line)).collect();
+        assert_eq!(lines, vec![Some(0), Some(1), Some(2), Some(3), Some(1), Some(4), Some(0), Some(5), None]);
+    }
+
+    #[test]
+    fn paths_of_nodes() {
+        let tree = tree(r#"{"a": [{"b c": 1}]} [true]"#);
+        let paths: Vec<String> = (0..5).map(|i| json::path_to_string(&tree.path(i))).collect();
+        assert_eq!(paths, vec![".", ".a", ".a[0]", ".a[0].\"b c\"", "."]);
+        assert_eq!(json::path_to_string(&tree.path(5)), ".[0]");
+    }
+}
@@ -1,5 +1,6 @@
 use ratatui::{
     layout::{
+        Alignment,
         Constraint,
         Direction,
         Layout,
@@ -11,10 +12,12 @@ use ratatui::{
         Style
     }, 
     terminal::Frame, 
-    text::Span,
+    text::{Line, Span},
     widgets::{
+        block::{Position, Title},
         Block,
         Borders,
+        Clear,
         Padding,
         Paragraph
     }
@@ -23,12 +26,24 @@ use ratatui::{
 use crate::{
     app::{
         App,
-        ErrorPanel
+        ConfirmWrite,
+        DiffMode,
+        DiffView,
+        ErrorPanel,
+        Focus,
+        Popup
     },
+    diff::DiffLine,
+    complete::Completion,
+    highlight::{self, QueryTokenKind},
+    json::{self, JsonChange, PathSegment},
+    history::HistorySearch,
+    saved::{SaveField, SaveForm, SavedPicker},
     tokens::{
         Token,
         TokenType
-    }
+    },
+    tree::{JsonTree, NodeValue, Row}
 };
 
 
@@ -38,14 +53,26 @@ pub fn render_app(app: &App, frame: &mut Frame) {
         None => 0,
         Some(err) => err.failure.lines().count().clamp(4, 64) as u16
     };
+    // the query pane grows with the query, up to half the screen.
+    // the borders and padding take up four lines
+    let query_len = (app.query_editor.lines().len() as u16 + 4)
+        .clamp(5, (frame.size().height / 2).max(5));
+    // a line beneath the result for where in it we are
+    let breadcrumb = app.breadcrumb();
+    let breadcrumb_len = if breadcrumb.is_some() { 1 } else { 0 };
     let layout = Layout::new(
         Direction::Vertical,
-        [Constraint::Fill(1), Constraint::Length(error_len), Constraint::Length(5)]
+        [Constraint::Fill(1), Constraint::Length(breadcrumb_len), Constraint::Length(error_len), Constraint::Length(query_len)]
     );
-    let &[filtered_content, error_messages, query_edit] = layout.split(frame.size()).as_ref() else {
+    let &[filtered_content, breadcrumb_line, error_messages, query_edit] = layout.split(frame.size()).as_ref() else {
         panic!("wrong number of values to unpack during layout")
     };
 
+    if let Some(breadcrumb) = breadcrumb {
+        let line = Line::styled(format!(" {breadcrumb}"), Style::default().fg(Color::Cyan));
+        frame.render_widget(Paragraph::new(line), breadcrumb_line);
+    }
+
     // Render the jq error (if any)
     if let Some(err) = app.error.as_ref() {
         render_error_panel(err, frame, error_messages);
@@ -61,40 +88,489 @@ pub fn render_app(app: &App, frame: &mut Frame) {
         // let text = Paragraph::new(app.filtered.as_str())
         //    .block(block);
 
-        let block = Block::bordered();
+        let mut block = Block::bordered();
+        if let Some(inspector) = &app.inspector {
+            let title = format!(
+                "stage {}/{}: {} (F6/F7 to step, F5 to leave)",
+                inspector.current + 1,
+                inspector.stages.len(),
+                inspector.stages[inspector.current].replace('\n', " "),
+            );
+            block = block.title(title);
+        }
+        if app.jq_client.is_running() {
+            block = block.title("still running…");
+        } else if !app.output_complete {
+            block = block.title("incomplete: the query stopped before all of its output arrived");
+        }
 
-        let w = app.scroll_text.widget()
-            .block(block);
+        if app.focus == Focus::Result {
+            let hint = match app.shown_tree() {
+                Some(_) => "space to fold, * to unfold all, 0-9 to fold at a depth, p/P to pick, t for text, esc for the query",
+                None => "v to select, p/P to pick the line, t for a tree, w to wrap, esc for the query",
+            };
+            block = block
+                .border_style(Style::default().fg(Color::Yellow))
+                .title(Title::from(hint).position(Position::Bottom));
+        }
 
-        frame.render_widget(w, filtered_content);
+        // where the text is scrolled to sideways, or that it wraps instead
+        let text = match &app.diff_view {
+            Some(diff) => Some(&diff.text),
+            None if app.shown_tree().is_none() => Some(&app.scroll_text),
+            None => None,
+        };
+        let position = match text {
+            Some(text) if text.wrap() => Some("wrapped".to_string()),
+            Some(text) if text.column_offset() > 0 => Some(format!("← col {}", text.column_offset() + 1)),
+            _ => None,
+        };
+        if let Some(position) = position {
+            block = block.title(Title::from(position).position(Position::Bottom).alignment(Alignment::Right));
+        }
+
+        match (&app.diff_view, app.shown_tree()) {
+            (Some(diff), _) => {
+                let title = match diff.mode {
+                    DiffMode::Lines => "changes from the input, line by line (F3 to compare by structure)",
+                    DiffMode::Structure => "changes from the input, by structure (F3 to see the output)",
+                };
+                let block = block
+                    .title(title)
+                    .title(Title::from(diff_counts(diff)).alignment(Alignment::Right));
+                let focused = app.focus == Focus::Result;
+                frame.render_widget(diff.text.widget().show_cursor(focused).block(block), filtered_content);
+            }
+            (None, Some(tree)) => render_tree(tree, block, frame, filtered_content),
+            (None, None) => {
+                let focused = app.focus == Focus::Result;
+                frame.render_widget(app.scroll_text.widget().show_cursor(focused).block(block), filtered_content);
+            }
+        }
     }
 
     // render the current query
-    {
-        let w = app.query_editor.widget();
-        frame.render_widget(w, query_edit);
+    render_query_editor(app, frame, query_edit);
+
+    // popups go over the filtered content
+    match &app.popup {
+        Some(Popup::HistorySearch(search)) => render_history_search(search, frame, filtered_content),
+        Some(Popup::SaveQuery(form)) => render_save_form(form, &app.query_content(), frame, filtered_content),
+        Some(Popup::SavedPicker(picker)) => render_saved_picker(picker, frame, filtered_content),
+        Some(Popup::ConfirmWrite(confirm)) => render_confirm_write(confirm, frame, filtered_content),
+        Some(Popup::Completion(completion)) => render_completion(completion, app, frame, filtered_content, query_edit),
+        None => {}
     }
+}
+
+fn render_tree(tree: &JsonTree, block: Block, frame: &mut Frame, area: Rect) {
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let lines: Vec<Line> = tree.visible_rows(inner.height as usize)
+        .map(|i| {
+            let line = tree_row_line(tree, tree.rows()[i]);
+            if i == tree.cursor() {
+                line.patch_style(Style::default().add_modifier(Modifier::REVERSED))
+            } else {
+                line
+            }
+        })
+        .collect();
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+/// A row of the tree, laid out the way jq prints it. Folded objects and arrays say how much is inside
+fn tree_row_line(tree: &JsonTree, row: Row) -> Line<'static> {
+    let node = tree.node(row.node);
+    let mut spans = vec![Span::raw("  ".repeat(node.depth))];
+    let summary = |count: usize, one: &str, many: &str| {
+        let noun = if count == 1 { one } else { many };
+        Span::styled(format!("… {count} {noun}"), Style::default().fg(Color::DarkGray))
+    };
+
+    if row.closing {
+        let bracket = match node.value {
+            NodeValue::Object { .. } => "}",
+            _ => "]",
+        };
+        spans.push(Span::raw(bracket));
+    } else {
+        if let Some(PathSegment::Key(key)) = &node.segment {
+            spans.push(Span::styled(json::quote(key), token_style(TokenType::String)));
+            spans.push(Span::raw(": "));
+        }
+        match &node.value {
+            NodeValue::Object { len: 0 } => spans.push(Span::raw("{}")),
+            NodeValue::Array { len: 0 } => spans.push(Span::raw("[]")),
+            NodeValue::Object { .. } if node.is_open() => spans.push(Span::raw("{")),
+            NodeValue::Array { .. } if node.is_open() => spans.push(Span::raw("[")),
+            NodeValue::Object { len } => spans.extend([Span::raw("{"), summary(*len, "key", "keys"), Span::raw("}")]),
+            NodeValue::Array { len } => spans.extend([Span::raw("["), summary(*len, "item", "items"), Span::raw("]")]),
+            NodeValue::Scalar { ty, text } => spans.push(Span::styled(text.clone(), token_style(*ty))),
+        }
+    }
+    if node.comma && (row.closing || !node.is_open()) {
+        spans.push(Span::raw(","));
+    }
+    Line::from(spans)
+}
+
+/// A rect in the middle of `area`, taking up the given percent of it
+fn centered_rect(percent_x: u16, percent_y: u16, area: Rect) -> Rect {
+    let width = area.width * percent_x / 100;
+    let height = area.height * percent_y / 100;
+    Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    }
+}
+
+/// The line where the user types, with a block for the cursor when it has focus
+fn input_line<'a>(label: &'a str, text: &'a str, focused: bool) -> Line<'a> {
+    let label_style = if focused {
+        Style::default().fg(Color::Yellow)
+    } else {
+        Style::default().fg(Color::DarkGray)
+    };
+    let mut spans = vec![Span::styled(label, label_style), Span::raw(text)];
+    if focused {
+        spans.push(Span::styled(" ", Style::default().add_modifier(Modifier::REVERSED)));
+    }
+    Line::from(spans)
+}
+
+/// The items of a picker list, scrolled so the selected one is in view
+fn picker_lines(items: Vec<Line<'static>>, selected: usize, height: usize) -> Vec<Line<'static>> {
+    let skip = selected.saturating_sub(height.saturating_sub(1));
+    items.into_iter()
+        .enumerate()
+        .skip(skip)
+        .take(height)
+        .map(|(i, line)| if i == selected {
+            line.patch_style(Style::default().add_modifier(Modifier::REVERSED))
+        } else {
+            line
+        })
+        .collect()
+}
+
+fn render_save_form(form: &SaveForm, query: &str, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(60, 50, area);
+    frame.render_widget(Clear, area);
+
+    let block = Block::bordered()
+        .title("save query (tab to switch, enter to save, esc to close)")
+        .padding(Padding::horizontal(1));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let lines = vec![
+        input_line("name: ", &form.name, form.field == SaveField::Name),
+        input_line("description: ", &form.description, form.field == SaveField::Description),
+        Line::default(),
+        Line::styled(query.replace('\n', " "), Style::default().fg(Color::DarkGray)),
+    ];
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+fn render_saved_picker(picker: &SavedPicker, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(80, 80, area);
+    frame.render_widget(Clear, area);
+
+    let block = Block::bordered()
+        .title("saved queries (enter to load, del to delete, esc to close)")
+        .title(Title::from(format!("{} matches", picker.matches.len())).alignment(Alignment::Right));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let items = picker.matches
+        .iter()
+        .map(|saved| {
+            let mut spans = vec![Span::styled(saved.name.clone(), Style::default().add_modifier(Modifier::BOLD))];
+            if !saved.description.is_empty() {
+                spans.push(Span::raw(format!(" - {}", saved.description)));
+            }
+            spans.push(Span::styled(format!("  {}", saved.query.replace('\n', " ")), Style::default().fg(Color::DarkGray)));
+            Line::from(spans)
+        })
+        .collect();
+
+    let mut lines = vec![input_line("> ", &picker.needle, true)];
+    lines.extend(picker_lines(items, picker.selected, inner.height.saturating_sub(1) as usize));
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+/// Colors a diff, `+` and green for added lines and `-` and red for removed ones.
+/// `None` are the unchanged lines that were left out
+pub fn diff_to_lines(diff: &[Option<DiffLine>]) -> Vec<Line<'static>> {
+    if !diff.iter().flatten().any(DiffLine::is_change) {
+        return vec![diff_note("no changes")];
+    }
+    diff.iter()
+        .map(|line| match line {
+            Some(DiffLine::Same(text)) => Line::raw(format!("  {text}")),
+            Some(DiffLine::Removed(text)) => Line::styled(format!("- {text}"), Style::default().fg(Color::Red)),
+            Some(DiffLine::Added(text)) => Line::styled(format!("+ {text}"), Style::default().fg(Color::Green)),
+            None => Line::styled("  …", Style::default().fg(Color::DarkGray)),
+        })
+        .collect()
+}
+
+/// Colors the changes of a structural diff like the lines of a diff, with changed values in yellow
+pub fn json_changes_to_lines(changes: &[JsonChange]) -> Vec<Line<'static>> {
+    if changes.is_empty() {
+        return vec![diff_note("no changes")];
+    }
+    changes.iter()
+        .map(|change| {
+            let color = match change {
+                JsonChange::Added { .. } => Color::Green,
+                JsonChange::Removed { .. } => Color::Red,
+                JsonChange::Changed { .. } => Color::Yellow,
+            };
+            Line::styled(change.to_string(), Style::default().fg(color))
+        })
+        .collect()
+}
+
+/// Stands in for a diff when there is none to show
+pub fn diff_note(note: &'static str) -> Line<'static> {
+    Line::styled(note, Style::default().fg(Color::DarkGray))
+}
+
+/// How many lines (or values) were added, removed and changed, colored like the diff
+fn diff_counts(diff: &DiffView) -> Line<'static> {
+    let mut spans = vec![
+        Span::styled(format!("+{}", diff.added), Style::default().fg(Color::Green)),
+        Span::raw(" "),
+        Span::styled(format!("-{}", diff.removed), Style::default().fg(Color::Red)),
+    ];
+    if diff.mode == DiffMode::Structure {
+        spans.push(Span::raw(" "));
+        spans.push(Span::styled(format!("~{}", diff.changed), Style::default().fg(Color::Yellow)));
+    }
+    Line::from(spans)
+}
+
+fn render_confirm_write(confirm: &ConfirmWrite, frame: &mut Frame, area: Rect) {
+    frame.render_widget(Clear, area);
 
+    let title = format!("write {}? (y to write, n to go back)", confirm.path.display());
+    let block = Block::bordered()
+        .title(title)
+        .title(Title::from(diff_counts(&confirm.diff)).alignment(Alignment::Right))
+        .border_style(Style::default().fg(Color::Yellow));
+
+    frame.render_widget(confirm.diff.text.widget().block(block), area);
+}
+
+/// Draws the completions just above the cursor in the query editor
+fn render_completion(completion: &Completion, app: &App, frame: &mut Frame, area: Rect, query_edit: Rect) {
+    const MAX_SHOWN: usize = 10;
+
+    let inner = app.query_editor.block().map_or(query_edit, |block| block.inner(query_edit));
+    let (_, cursor_col) = app.query_editor.cursor();
+
+    let widest = completion.items.iter().map(|item| item.label.chars().count()).max().unwrap_or(0);
+    let width = (widest as u16 + 2).min(area.width);
+    let height = (completion.items.len().min(MAX_SHOWN) as u16 + 2).min(area.height);
+    let x = (inner.x + cursor_col.saturating_sub(completion.replace_len) as u16)
+        .min(area.right().saturating_sub(width));
+    let popup = Rect {
+        x,
+        y: area.bottom().saturating_sub(height),
+        width,
+        height,
+    };
+    frame.render_widget(Clear, popup);
+
+    let block = Block::bordered().border_style(Style::default().fg(Color::DarkGray));
+    let inner = block.inner(popup);
+    frame.render_widget(block, popup);
+
+    let items = completion.items.iter().map(|item| Line::raw(item.label.clone())).collect();
+    let lines = picker_lines(items, completion.selected, inner.height as usize);
+    frame.render_widget(Paragraph::new(lines), inner);
+}
+
+fn render_history_search(search: &HistorySearch, frame: &mut Frame, area: Rect) {
+    let area = centered_rect(80, 80, area);
+    frame.render_widget(Clear, area);
+
+    let block = Block::bordered()
+        .title("history (enter to load, esc to close)")
+        .title(Title::from(format!("{} matches", search.matches.len())).alignment(Alignment::Right));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let items = search.matches
+        .iter()
+        .map(|query| Line::raw(query.replace('\n', " ")))
+        .collect();
+
+    let mut lines = vec![input_line("> ", &search.needle, true)];
+    lines.extend(picker_lines(items, search.selected, inner.height.saturating_sub(1) as usize));
+
+    frame.render_widget(Paragraph::new(lines), inner);
 }
 
 pub fn set_query_editor_styles(app: &mut App) {
     let line_style = Style::default();
     app.query_editor.set_cursor_line_style(line_style);
+    let cursor_style = match app.focus {
+        Focus::Query => Style::default().add_modifier(Modifier::REVERSED),
+        Focus::Result => Style::default(),
+    };
+    app.query_editor.set_cursor_style(cursor_style);
 
     let block_style = match &app.error {
         Some(_) => Style::default().fg(Color::Red),
         None => Style::default(),
     };
 
-    let block = Block::default()
+    let backend = &app.jq_client.backend;
+    let mut title = std::iter::once(backend.name())
+        .chain(app.jq_options.flags())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if app.jq_client.answered_natively {
+        title.push_str(" (native)");
+    }
+    if app.live {
+        title.push_str(" (live)");
+    }
+
+    let version = backend.version
+        .clone()
+        .unwrap_or_else(|| format!("{} not found", backend.exe.display()));
+
+    let mut block = Block::default()
         .borders(Borders::ALL)
-        .title("jq")
+        .title(title)
+        .title(Title::from(version).alignment(Alignment::Right))
         .padding(Padding::vertical(1))
         .style(block_style);
+    if let Some(hint) = app.signature_hint() {
+        block = block.title(Title::from(hint).position(Position::Bottom));
+    }
+    if let Some(notice) = &app.notice {
+        block = block.title(Title::from(notice.clone()).position(Position::Bottom).alignment(Alignment::Right));
+    }
 
     app.query_editor.set_block(block);
 }
 
+/// The style for the part of the query that the error points at
+fn error_location_style() -> Style {
+    Style::default()
+        .fg(Color::Red)
+        .add_modifier(Modifier::BOLD | Modifier::UNDERLINED)
+}
+
+/// The style for each part of the query
+fn query_token_style(kind: QueryTokenKind) -> Style {
+    match kind {
+        QueryTokenKind::Plain | QueryTokenKind::Bracket => Style::default(),
+        QueryTokenKind::Path => Style::default().fg(Color::Cyan),
+        QueryTokenKind::Variable => Style::default().fg(Color::Yellow),
+        QueryTokenKind::Function => Style::default().fg(Color::LightBlue),
+        QueryTokenKind::Keyword => Style::default().fg(Color::Magenta).add_modifier(Modifier::BOLD),
+        QueryTokenKind::Format => Style::default().fg(Color::LightYellow),
+        QueryTokenKind::String => Style::default().fg(Color::Green),
+        QueryTokenKind::Interpolation => Style::default().fg(Color::LightGreen).add_modifier(Modifier::BOLD),
+        QueryTokenKind::Number => Style::default().fg(Color::Blue),
+        QueryTokenKind::Operator => Style::default().fg(Color::LightMagenta),
+        QueryTokenKind::Comment => Style::default().fg(Color::DarkGray).add_modifier(Modifier::ITALIC),
+    }
+}
+
+/// The style for a bracket without a partner
+fn unbalanced_bracket_style() -> Style {
+    Style::default()
+        .fg(Color::White)
+        .bg(Color::Red)
+}
+
+/// Draws the query ourselves (rather than with the text area widget) so that we can style parts of it
+fn render_query_editor(app: &App, frame: &mut Frame, size: Rect) {
+    let block = app.query_editor.block().cloned().unwrap_or_default();
+    let inner = block.inner(size);
+    frame.render_widget(block, size);
+
+    let (cursor_row, cursor_col) = app.query_editor.cursor();
+    let location = app.error.as_ref().and_then(|err| err.location);
+
+    let query = app.query_editor.lines().join("\n");
+    let highlighted = highlight::highlight(&query);
+
+    // where each line starts in the whole query, counting in chars
+    let mut line_start = 0;
+    let lines: Vec<Line> = app.query_editor.lines()
+        .iter()
+        .enumerate()
+        .map(|(row, text)| {
+            let start = line_start;
+            line_start += text.chars().count() + 1;
+            let mut styled: Vec<(char, Style)> = text.chars()
+                .enumerate()
+                .map(|(col, ch)| {
+                    let at = start + col;
+                    let mut style = query_token_style(highlighted.kinds[at]);
+                    if highlighted.unbalanced.contains(&at) {
+                        style = unbalanced_bracket_style();
+                    }
+                    let in_error = location.is_some_and(|loc| loc.line == row && (loc.start..loc.end).contains(&col));
+                    if in_error {
+                        style = style.patch(error_location_style());
+                    }
+                    (ch, style)
+                })
+                .collect();
+
+            // draw the cursor as a reversed cell, the same as the text area does
+            if row == cursor_row {
+                if cursor_col >= styled.len() {
+                    styled.push((' ', Style::default()));
+                }
+                let cell = &mut styled[cursor_col];
+                cell.1 = cell.1.add_modifier(Modifier::REVERSED);
+            }
+
+            styled_chars_to_line(styled)
+        })
+        .collect();
+
+    // keep the cursor in view
+    let row_offset = cursor_row.saturating_sub(inner.height.saturating_sub(1) as usize);
+    let col_offset = cursor_col.saturating_sub(inner.width.saturating_sub(1) as usize);
+
+    let para = Paragraph::new(lines)
+        .scroll((row_offset as u16, col_offset as u16));
+
+    frame.render_widget(para, inner);
+}
+
+/// Groups runs of chars with the same style into spans
+fn styled_chars_to_line(styled: Vec<(char, Style)>) -> Line<'static> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut run = String::new();
+    let mut run_style = None;
+    for (ch, style) in styled {
+        if run_style.is_some_and(|s| s != style) {
+            spans.push(Span::styled(std::mem::take(&mut run), run_style.unwrap_or_default()));
+        }
+        run.push(ch);
+        run_style = Some(style);
+    }
+    if !run.is_empty() {
+        spans.push(Span::styled(run, run_style.unwrap_or_default()));
+    }
+    Line::from(spans)
+}
+
 fn render_error_panel(err: &ErrorPanel, frame: &mut Frame, size: Rect) {
     let border_style = Style::default()
         .fg(Color::Red)
@@ -114,15 +590,19 @@ fn render_error_panel(err: &ErrorPanel, frame: &mut Frame, size: Rect) {
 
 
 pub fn token_to_span<'a>(tok: &Token<'a>) -> Span<'static> {
-    let style = match tok.tty {
+    Span::styled(tok.lex.to_string(), token_style(tok.tty))
+}
+
+fn token_style(tty: TokenType) -> Style {
+    match tty {
         TokenType::OpenBrace | TokenType::CloseBrace  | TokenType::OpenBracket 
             | TokenType::CloseBracket  | TokenType::Comma  | TokenType::Colon  
             | TokenType::Whitespace  | TokenType::Newline => Style::default(),
         TokenType::String => Style::default().fg(Color::Green),
         TokenType::Number => Style::default().fg(Color::Blue),
         TokenType::Boolean => Style::default().fg(Color::Yellow),
+        TokenType::Null => Style::default().fg(Color::DarkGray),
         TokenType::InvalidChar => Style::default().fg(Color::White).bg(Color::Red),
         TokenType::Eof => Style::default(),
-    };
-    Span::styled(tok.lex.to_string(), style)
+    }
 }